@@ -1,4 +1,10 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::utils::{
+    generate_id::{CheckDigit, StopIdTemplate},
+    geo::{BoundingBox, parse_distance_m, parse_point},
+    xlsx::ReadOptions,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -7,6 +13,10 @@ use clap::{Args, Parser, Subcommand};
     about = "Manage data model with Excel and format"
 )]
 pub struct Cli {
+    /// Config file profile to use; VEZA_PROFILE or the file's default_profile
+    /// otherwise.
+    #[arg(long = "profile", global = true)]
+    pub profile: Option<String>,
     #[command(subcommand)]
     pub model: ModelCommand,
 }
@@ -14,7 +24,29 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum ModelCommand {
     #[command(subcommand)]
-    Stop(StopCommand),
+    Stop(Box<StopCommand>),
+    /// Stores, removes and shows the API tokens of a profile.
+    #[command(subcommand)]
+    Auth(AuthCommand),
+    /// Checks the config, tokens and connections, and suggests fixes.
+    Doctor,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuthCommand {
+    /// Prompts for the API and Mapbox tokens and stores them encrypted.
+    Login,
+    /// Removes the stored tokens of the profile.
+    Logout(LogoutArgs),
+    /// Shows where each token of the profile comes from.
+    Status,
+}
+
+#[derive(Args, Debug)]
+pub struct LogoutArgs {
+    /// Removes the tokens of every profile, with the credentials file and key.
+    #[arg(long = "all")]
+    pub all: bool,
 }
 
 #[derive(Subcommand, Debug)]
@@ -24,6 +56,40 @@ pub enum StopCommand {
     /// Formats stop data from different sources and optionally writes to an Excel file.
     #[command(subcommand)]
     Format(FormatCommand),
+    /// Applies edits from an exported Excel file, sending only the changed stops.
+    Import(ImportArgs),
+    /// Creates new stops from the rows of an Excel file that have no ID.
+    Create(CreateArgs),
+    /// Deletes stops by ID.
+    Delete(DeleteArgs),
+    /// Checks stops for data-quality problems and writes a per-rule report.
+    Validate(ValidateArgs),
+    /// Finds duplicate stops and merges them after review.
+    #[command(subcommand)]
+    Dedupe(DedupeCommand),
+    /// Tags each stop with the GeoJSON zone polygon it falls in.
+    Zone(ZoneArgs),
+    /// Groups nearby stops into clusters and writes a Cluster column.
+    Cluster(ClusterArgs),
+    /// Writes stops in a visiting sequence from a depot, optionally renumbering StopIDs.
+    Order(OrderArgs),
+    /// Works with StopIDs without touching the backend.
+    #[command(subcommand)]
+    Id(IdCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DedupeCommand {
+    /// Groups stops that are close together or have similar addresses and writes a review sheet.
+    Find(DedupeFindArgs),
+    /// Deletes the stops not marked Keep in a reviewed sheet.
+    Apply(DedupeApplyArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IdCommand {
+    /// Checks the check characters of a list of StopIDs.
+    Verify(VerifyIdArgs),
 }
 
 #[derive(Subcommand, Debug)]
@@ -31,17 +97,96 @@ pub enum FormatCommand {
     /// Pulls stops from the backend API, formats them, and optionally writes to an Excel file.
     Pull(PullFormatArgs),
     /// Reads stops from an Excel file, formats them, and optionally writes to a new Excel file.
-    ReadXlsx(ReadXlsxFormatArgs),
+    ReadXlsx(Box<ReadXlsxFormatArgs>),
 
     StopID(StopIDArgs),
 }
 
 #[derive(Args, Debug)]
 pub struct StopIDArgs {
-    #[arg(short = 'p', long = "pattern", default_value = "ST000000")]
-    pub pattern: String,
+    /// StopID pattern: ST000000, or a template such as {ZONE}-{SEQ:05},
+    /// {CITY3}{SEQ:04} or ST{YEAR}{SEQ:06}. Each zone, city... gets its own
+    /// sequence; {ZONE} needs --zones, {CITY} and {CLUSTER} --fields-from.
+    /// Defaults to the profile's pattern, then ST000000.
+    #[arg(short = 'p', long = "pattern", value_parser = StopIdTemplate::parse)]
+    pub pattern: Option<StopIdTemplate>,
+    /// Organization to renumber, defaulting to the profile's; also the value
+    /// of {ORG}.
     #[arg(short = 'o', long = "organization")]
-    pub organization_id: String,
+    pub organization_id: Option<String>,
+    /// GeoJSON zones that give each stop its {ZONE}.
+    #[arg(short = 'z', long = "zones")]
+    pub zones_file: Option<String>,
+    /// Zone feature property holding the zone name.
+    #[arg(
+        long = "zone-property",
+        default_value = "name",
+        requires = "zones_file"
+    )]
+    pub zone_property: String,
+    /// Spreadsheet of the organization's stops, e.g. from `stop cluster` or
+    /// `format read-xlsx`, whose City and Cluster columns give each stop its
+    /// {CITY} and {CLUSTER}. Rows match stops by ID.
+    #[arg(long = "fields-from")]
+    pub fields_file: Option<String>,
+    /// Numbering order: backend, geo (nearby stops get adjacent IDs),
+    /// address, or column:<field> (id, stopId, address, latitude, longitude).
+    #[arg(long = "order-by", default_value = "backend", value_parser = parse_order_by)]
+    pub order_by: OrderBy,
+    /// Append a check character to every ID: luhn, mod11 or damm.
+    #[arg(long = "checksum")]
+    pub checksum: Option<CheckDigit>,
+    /// Only number stops without a StopID; existing ones are kept and skipped.
+    #[arg(long = "only-missing")]
+    pub only_missing: bool,
+    /// Keep StopIDs that already match the pattern and number the rest after
+    /// the highest sequence in use.
+    #[arg(long = "continue-from-max")]
+    pub continue_from_max: bool,
+    /// Write the plan to the report without sending any changes.
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+    /// Excel report of every planned change and whether it was applied.
+    #[arg(short = 'r', long = "report", default_value = "stop_id_report.xlsx")]
+    pub report_file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct VerifyIdArgs {
+    /// StopIDs to check.
+    pub ids: Vec<String>,
+    /// Also check the StopIDs in this text file, one per line.
+    #[arg(short = 'f', long = "file")]
+    pub file_path: Option<String>,
+    /// Check character algorithm the IDs were generated with.
+    #[arg(long = "checksum")]
+    pub checksum: CheckDigit,
+}
+
+/// Order in which `format stop-id` hands out IDs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderBy {
+    Backend,
+    Geo,
+    Address,
+    Column(String),
+}
+
+fn parse_order_by(s: &str) -> Result<OrderBy, String> {
+    match s.trim() {
+        "backend" => Ok(OrderBy::Backend),
+        "geo" => Ok(OrderBy::Geo),
+        "address" => Ok(OrderBy::Address),
+        other => match other.strip_prefix("column:") {
+            Some(field) if !field.trim().is_empty() => {
+                Ok(OrderBy::Column(field.trim().to_string()))
+            }
+            _ => Err(format!(
+                "Expected backend, geo, address or column:<field>, got '{}'",
+                s
+            )),
+        },
+    }
 }
 
 #[derive(Args, Debug)]
@@ -49,6 +194,26 @@ pub struct ExportArgs {
     /// Output Excel file name.
     #[arg(short = 'f', long = "file", default_value = "output.xlsx")]
     pub file_name: String,
+    #[command(flatten)]
+    pub spatial_args: SpatialFilterArgs,
+}
+
+/// Client-side spatial filters shared by export and format commands. Stops
+/// without coordinates are dropped when any filter is set.
+#[derive(Args, Debug)]
+pub struct SpatialFilterArgs {
+    /// Only keep stops inside this box, as minLon,minLat,maxLon,maxLat.
+    #[arg(long = "bbox", allow_hyphen_values = true)]
+    pub bbox: Option<BoundingBox>,
+    /// Only keep stops within --radius of this point, as lat,lon.
+    #[arg(long = "near", value_parser = parse_point, allow_hyphen_values = true, requires = "radius_m")]
+    pub near: Option<(f64, f64)>,
+    /// Radius around --near, e.g. 2km or 500m.
+    #[arg(long = "radius", value_parser = parse_distance_m, requires = "near")]
+    pub radius_m: Option<f64>,
+    /// Only keep stops inside a polygon of this GeoJSON file.
+    #[arg(long = "within")]
+    pub within: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -63,7 +228,7 @@ pub struct PullFormatArgs {
 
 #[derive(Args, Debug)]
 pub struct ReadXlsxFormatArgs {
-    /// Path to the Excel file to read stops from.
+    /// Path to the spreadsheet to read stops from (.xlsx, .xls or .ods).
     #[arg(short = 'f', long = "file", default_value = "input.xlsx")]
     pub file_path: String,
     /// Output Excel file name after formatting (optional).
@@ -72,4 +237,314 @@ pub struct ReadXlsxFormatArgs {
 
     #[arg(short = 'u', long = "update-backend", default_value_t = false)]
     pub update_backend: bool,
+    #[command(flatten)]
+    pub sheet_args: SheetArgs,
+    /// Write into a copy of the input workbook, updating only the Address,
+    /// Latitude and Longtitude cells and appending Status and Confidence columns.
+    #[arg(long = "preserve-source", default_value_t = false)]
+    pub preserve_source: bool,
+    /// Swap latitude and longitude where they appear reversed and flag the row,
+    /// instead of re-geocoding it or keeping the bad point. Points outside
+    /// --expect-bbox that fall inside it when swapped also count as swapped.
+    #[arg(long = "fix-swapped", default_value_t = false)]
+    pub fix_swapped: bool,
+    /// Area the stops are expected in, as minLon,minLat,maxLon,maxLat. Used to
+    /// detect swapped coordinates; unlike --bbox it drops no stops. Defaults
+    /// to the profile's bbox.
+    #[arg(
+        long = "expect-bbox",
+        allow_hyphen_values = true,
+        requires = "fix_swapped"
+    )]
+    pub expect_bbox: Option<BoundingBox>,
+    #[command(flatten)]
+    pub spatial_args: SpatialFilterArgs,
+    /// Clean addresses before geocoding: drop notes in brackets and placeholders
+    /// like "N/A", remove repeated parts, expand abbreviations.
+    #[arg(long = "normalize-address", default_value_t = false)]
+    pub normalize_address: bool,
+    /// Appended to cleaned addresses that do not contain it, e.g. "Bogotá, Colombia".
+    #[arg(long = "address-suffix", requires = "normalize_address")]
+    pub address_suffix: Option<String>,
+    /// Additional abbreviation to expand, as ABBR=Expansion. Can be repeated.
+    #[arg(long = "abbreviation", value_parser = parse_key_value, requires = "normalize_address")]
+    pub abbreviations: Vec<(String, String)>,
+    /// Send the geocoded street, postcode, city, region and country of stops
+    /// that have an ID to the backend, after the output is written.
+    #[arg(long = "push-components", default_value_t = false)]
+    pub push_components: bool,
+    /// Show the component updates without sending them to the backend.
+    #[arg(
+        long = "dry-run",
+        default_value_t = false,
+        requires = "push_components"
+    )]
+    pub dry_run: bool,
+    /// Send the component updates without asking for confirmation.
+    #[arg(
+        short = 'y',
+        long = "yes",
+        default_value_t = false,
+        requires = "push_components"
+    )]
+    pub yes: bool,
+    /// Geocode through the Mapbox batch endpoint, up to 1000 addresses per
+    /// request, retrying unresolved addresses one by one.
+    #[arg(long = "batch", default_value_t = false, conflicts_with = "gazetteer")]
+    pub batch: bool,
+    /// After geocoding, move stops onto the nearest drivable road of this OSM
+    /// PBF extract, recording the original point and snap distance.
+    #[arg(long = "snap-to-roads")]
+    pub snap_to_roads: Option<String>,
+    /// Snaps farther than this many meters are flagged for review.
+    #[arg(
+        long = "snap-max-distance",
+        default_value_t = 50.0,
+        requires = "snap_to_roads"
+    )]
+    pub snap_max_distance: f64,
+    /// Geocode offline against this file instead of Mapbox: an OpenAddresses
+    /// CSV or a spreadsheet of already geocoded stops.
+    #[arg(long = "gazetteer")]
+    pub gazetteer: Option<String>,
+    /// Lowest address similarity (0.0 to 1.0) accepted as a gazetteer match.
+    #[arg(
+        long = "gazetteer-min-similarity",
+        default_value_t = 0.85,
+        requires = "gazetteer"
+    )]
+    pub gazetteer_min_similarity: f64,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected KEY=VALUE, got '{}'", s))?;
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+/// Where to read stops from: the backend, optionally limited to one
+/// organization, or a spreadsheet.
+#[derive(Args, Debug)]
+pub struct StopSourceArgs {
+    /// Read stops from this spreadsheet instead of the backend.
+    #[arg(short = 'f', long = "file")]
+    pub file_path: Option<String>,
+    #[command(flatten)]
+    pub sheet_args: SheetArgs,
+    /// Only fetch stops of this organization from the backend.
+    #[arg(long = "organization", conflicts_with = "file_path")]
+    pub organization_id: Option<String>,
+}
+
+/// Worksheet selection shared by commands that read stops from a spreadsheet.
+#[derive(Args, Debug)]
+pub struct SheetArgs {
+    /// Worksheet to read, by name or zero-based index (defaults to the first sheet).
+    #[arg(short = 's', long = "sheet", conflicts_with = "all_sheets")]
+    pub sheet: Option<String>,
+    /// Read every worksheet that has the expected headers.
+    #[arg(long = "all-sheets", default_value_t = false)]
+    pub all_sheets: bool,
+    /// 1-based row number of the header row.
+    #[arg(long = "header-row", default_value_t = 1)]
+    pub header_row: u32,
+}
+
+impl SheetArgs {
+    pub fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            sheet: self.sheet.clone(),
+            all_sheets: self.all_sheets,
+            header_row: self.header_row,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// Path to the edited spreadsheet, as written by `stop export`.
+    #[arg(short = 'f', long = "file")]
+    pub file_path: String,
+    #[command(flatten)]
+    pub sheet_args: SheetArgs,
+    /// Show the changes without sending them to the backend.
+    #[arg(long = "dry-run", default_value_t = false)]
+    pub dry_run: bool,
+    /// Apply the changes without asking for confirmation.
+    #[arg(short = 'y', long = "yes", default_value_t = false)]
+    pub yes: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CreateArgs {
+    /// Path to the spreadsheet with the new stops; rows with an ID are skipped.
+    #[arg(short = 'f', long = "file")]
+    pub file_path: String,
+    #[command(flatten)]
+    pub sheet_args: SheetArgs,
+    /// Organization to connect the new stops to.
+    #[arg(short = 'o', long = "organization")]
+    pub organization_id: Option<String>,
+    /// Write the created stops, with their new IDs, to this Excel file.
+    #[arg(long = "output")]
+    pub output_file: Option<String>,
+    /// Show the stops that would be created without sending them.
+    #[arg(long = "dry-run", default_value_t = false)]
+    pub dry_run: bool,
+    /// Create the stops without asking for confirmation.
+    #[arg(short = 'y', long = "yes", default_value_t = false)]
+    pub yes: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DeleteArgs {
+    /// Comma-separated stop IDs to delete.
+    #[arg(
+        long = "ids",
+        value_delimiter = ',',
+        required_unless_present = "file_path"
+    )]
+    pub ids: Vec<String>,
+    /// Spreadsheet whose ID column lists the stops to delete.
+    #[arg(short = 'f', long = "file", conflicts_with = "ids")]
+    pub file_path: Option<String>,
+    #[command(flatten)]
+    pub sheet_args: SheetArgs,
+    /// Show the stops that would be deleted without deleting them.
+    #[arg(long = "dry-run", default_value_t = false)]
+    pub dry_run: bool,
+    /// Delete the stops without asking for confirmation.
+    #[arg(short = 'y', long = "yes", default_value_t = false)]
+    pub yes: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DedupeFindArgs {
+    #[command(flatten)]
+    pub source: StopSourceArgs,
+    /// Stops closer than this many meters are duplicates.
+    #[arg(short = 'd', long = "distance", default_value_t = 25.0)]
+    pub max_distance_m: f64,
+    /// Stops whose normalized addresses are at least this similar (0.0-1.0) are duplicates.
+    #[arg(long = "similarity", default_value_t = 0.9)]
+    pub min_similarity: f64,
+    /// Output Excel file for the review sheet.
+    #[arg(short = 'o', long = "output", default_value = "duplicates.xlsx")]
+    pub output_file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct DedupeApplyArgs {
+    /// Review sheet written by `stop dedupe find`, with the Keep column edited as needed.
+    #[arg(short = 'f', long = "file")]
+    pub file_path: String,
+    /// Show the merge without changing the backend.
+    #[arg(long = "dry-run", default_value_t = false)]
+    pub dry_run: bool,
+    /// Merge without asking for confirmation.
+    #[arg(short = 'y', long = "yes", default_value_t = false)]
+    pub yes: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ZoneArgs {
+    #[command(flatten)]
+    pub source: StopSourceArgs,
+    /// GeoJSON file with one Polygon or MultiPolygon feature per zone.
+    #[arg(short = 'z', long = "zones")]
+    pub zones_file: String,
+    /// Feature property holding the zone name.
+    #[arg(long = "zone-property", default_value = "name")]
+    pub zone_property: String,
+    /// Output Excel file with a Zone column.
+    #[arg(short = 'o', long = "output", default_value = "zoned_stops.xlsx")]
+    pub output_file: String,
+    /// Also write the zone name to this backend stop field, e.g. zone or tag.
+    #[arg(long = "push-field")]
+    pub push_field: Option<String>,
+    /// Show the zone updates without sending them to the backend.
+    #[arg(long = "dry-run", default_value_t = false, requires = "push_field")]
+    pub dry_run: bool,
+    /// Send the zone updates without asking for confirmation.
+    #[arg(
+        short = 'y',
+        long = "yes",
+        default_value_t = false,
+        requires = "push_field"
+    )]
+    pub yes: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ClusterMethod {
+    /// A fixed number of clusters (--clusters).
+    Kmeans,
+    /// Clusters of dense stops (--eps, --min-points); isolated stops become noise.
+    Dbscan,
+}
+
+#[derive(Args, Debug)]
+pub struct ClusterArgs {
+    #[command(flatten)]
+    pub source: StopSourceArgs,
+    #[arg(short = 'm', long = "method", value_enum, default_value_t = ClusterMethod::Kmeans)]
+    pub method: ClusterMethod,
+    /// Number of clusters for k-means.
+    #[arg(short = 'k', long = "clusters", default_value_t = 8)]
+    pub clusters: usize,
+    /// Neighbourhood radius for DBSCAN, e.g. 500m or 1km.
+    #[arg(long = "eps", value_parser = parse_distance_m, default_value = "500m")]
+    pub eps_m: f64,
+    /// Stops within --eps, itself included, needed to start a DBSCAN cluster.
+    #[arg(long = "min-points", default_value_t = 3)]
+    pub min_points: usize,
+    /// Output Excel file with a Cluster column.
+    #[arg(short = 'o', long = "output", default_value = "clustered_stops.xlsx")]
+    pub output_file: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OrderMethod {
+    /// Always visit the closest remaining stop.
+    NearestNeighbour,
+    /// Nearest neighbour, then remove crossings with 2-opt.
+    TwoOpt,
+}
+
+#[derive(Args, Debug)]
+pub struct OrderArgs {
+    #[command(flatten)]
+    pub source: StopSourceArgs,
+    /// Start of the route, as lat,lon.
+    #[arg(long = "depot", value_parser = parse_point, allow_hyphen_values = true)]
+    pub depot: (f64, f64),
+    #[arg(short = 'm', long = "method", value_enum, default_value_t = OrderMethod::TwoOpt)]
+    pub method: OrderMethod,
+    /// Renumber StopIDs in visiting order from this pattern, e.g. ST000000
+    /// or {ZONE}-{SEQ:04}.
+    #[arg(short = 'p', long = "renumber", value_parser = StopIdTemplate::parse)]
+    pub renumber: Option<StopIdTemplate>,
+    /// Append a check character to renumbered IDs: luhn, mod11 or damm.
+    #[arg(long = "checksum", requires = "renumber")]
+    pub checksum: Option<CheckDigit>,
+    /// Output Excel file with the stops in visiting order and a Sequence column.
+    #[arg(short = 'o', long = "output", default_value = "ordered_stops.xlsx")]
+    pub output_file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub source: StopSourceArgs,
+    /// Area stops are expected in, as minLon,minLat,maxLon,maxLat. Defaults
+    /// to the profile's bbox.
+    #[arg(long = "bbox", allow_hyphen_values = true)]
+    pub bbox: Option<BoundingBox>,
+    /// Expected StopID pattern, e.g. ST000000 or {ZONE}-{SEQ:05}.
+    #[arg(short = 'p', long = "pattern", value_parser = StopIdTemplate::parse)]
+    pub pattern: Option<StopIdTemplate>,
+    /// Output Excel file for the report.
+    #[arg(short = 'o', long = "output", default_value = "validation.xlsx")]
+    pub output_file: String,
 }
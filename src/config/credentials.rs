@@ -0,0 +1,423 @@
+use std::{
+    cell::OnceCell,
+    collections::BTreeMap,
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, rand_core::RngCore},
+};
+use secrecy::{
+    ExposeSecret, SecretString,
+    zeroize::{Zeroize, Zeroizing},
+};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::ConfigError;
+
+/// Credentials are stored under this name when no profile is in use.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Passphrase of the credentials file, for machines without an OS keyring
+/// and no terminal to ask on.
+pub const PASSPHRASE_VAR: &str = "VEZA_CREDENTIALS_PASSPHRASE";
+
+/// Keyring service the entries are stored under, one per profile.
+const SERVICE: &str = "veza-cli";
+
+/// Start of an encrypted credentials file, followed by the salt and nonce.
+const MAGIC: &[u8] = b"VEZA2";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Tokens saved by `auth login` for one profile.
+#[derive(Debug, Default, Clone)]
+pub struct StoredTokens {
+    pub api_token: Option<SecretString>,
+    pub mapbox_token: Option<SecretString>,
+}
+
+impl StoredTokens {
+    pub fn is_empty(&self) -> bool {
+        self.api_token.is_none() && self.mapbox_token.is_none()
+    }
+}
+
+/// Where the tokens of a profile are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Keyring,
+    File,
+}
+
+/// Plain form of the tokens, only alive while storing or reading them.
+#[derive(Serialize, Deserialize, Default)]
+struct PlainTokens {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mapbox_token: Option<String>,
+}
+
+impl From<PlainTokens> for StoredTokens {
+    fn from(plain: PlainTokens) -> Self {
+        StoredTokens {
+            api_token: plain.api_token.map(SecretString::from),
+            mapbox_token: plain.mapbox_token.map(SecretString::from),
+        }
+    }
+}
+
+impl From<&StoredTokens> for PlainTokens {
+    fn from(tokens: &StoredTokens) -> Self {
+        let expose = |token: &Option<SecretString>| {
+            token
+                .as_ref()
+                .map(|token| token.expose_secret().to_string())
+        };
+        PlainTokens {
+            api_token: expose(&tokens.api_token),
+            mapbox_token: expose(&tokens.mapbox_token),
+        }
+    }
+}
+
+/// Tokens per profile, kept in the OS keyring (Keychain, Credential Manager
+/// or a Secret Service such as GNOME Keyring) when one is reachable.
+///
+/// Otherwise, e.g. on a headless server, they go to a file encrypted with a
+/// key derived from a passphrase (Argon2id), asked for on the terminal or
+/// read from `VEZA_CREDENTIALS_PASSPHRASE`. Copies of the file, backups
+/// included, are useless without the passphrase; anything running as the
+/// user while the passphrase is in the environment can still read them.
+pub struct CredentialStore {
+    path: PathBuf,
+    keyring: bool,
+    passphrase: OnceCell<SecretString>,
+}
+
+impl CredentialStore {
+    pub fn new(dir: &Path) -> Self {
+        CredentialStore {
+            path: dir.join("credentials"),
+            keyring: true,
+            passphrase: OnceCell::new(),
+        }
+    }
+
+    /// The fallback file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The tokens stored for `profile` and where they are, the keyring
+    /// first.
+    pub fn find(&self, profile: &str) -> Result<Option<(Location, StoredTokens)>, ConfigError> {
+        if let Some(entry) = self.entry(profile)? {
+            match entry.get_password() {
+                Ok(mut json) => {
+                    let plain = serde_json::from_str::<PlainTokens>(&json);
+                    json.zeroize();
+                    let plain = plain.map_err(|e| keyring_error(profile, e))?;
+                    return Ok(Some((Location::Keyring, plain.into())));
+                }
+                Err(keyring::Error::NoEntry) => {}
+                Err(e) if unavailable(&e) => {
+                    debug!("No OS keyring, using the credentials file: {}", e)
+                }
+                Err(e) => return Err(keyring_error(profile, e)),
+            }
+        }
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        Ok(self
+            .read_file()?
+            .remove(profile)
+            .map(|tokens| (Location::File, tokens)))
+    }
+
+    /// The tokens stored for `profile`; none when nothing was stored.
+    pub fn load(&self, profile: &str) -> Result<StoredTokens, ConfigError> {
+        Ok(self
+            .find(profile)?
+            .map(|(_, tokens)| tokens)
+            .unwrap_or_default())
+    }
+
+    /// Stores `tokens` for `profile`, in the keyring if there is one.
+    pub fn save(&self, profile: &str, tokens: &StoredTokens) -> Result<Location, ConfigError> {
+        if let Some(entry) = self.entry(profile)? {
+            let mut json = serde_json::to_string(&PlainTokens::from(tokens))
+                .map_err(|e| keyring_error(profile, e))?;
+            let stored = entry.set_password(&json);
+            json.zeroize();
+            match stored {
+                Ok(()) => return Ok(Location::Keyring),
+                Err(e) if unavailable(&e) => {
+                    debug!("No OS keyring, using the credentials file: {}", e)
+                }
+                Err(e) => return Err(keyring_error(profile, e)),
+            }
+        }
+        let mut all = match self.path.exists() {
+            true => self.read_file()?,
+            false => BTreeMap::new(),
+        };
+        all.insert(profile.to_string(), tokens.clone());
+        self.write_file(&all)?;
+        Ok(Location::File)
+    }
+
+    /// Removes what is stored for `profile`; false when there was nothing.
+    pub fn remove(&self, profile: &str) -> Result<bool, ConfigError> {
+        let mut removed = self.remove_entry(profile)?;
+        if self.path.exists() {
+            let mut all = self.read_file()?;
+            if all.remove(profile).is_some() {
+                removed = true;
+                match all.is_empty() {
+                    true => self.remove_file()?,
+                    false => self.write_file(&all)?,
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Removes the keyring entries of `profiles` and the whole file, which
+    /// needs no passphrase.
+    pub fn remove_all(&self, profiles: &[String]) -> Result<(), ConfigError> {
+        for profile in profiles {
+            self.remove_entry(profile)?;
+        }
+        self.remove_file()
+    }
+
+    fn entry(&self, profile: &str) -> Result<Option<keyring::Entry>, ConfigError> {
+        if !self.keyring {
+            return Ok(None);
+        }
+        match keyring::Entry::new(SERVICE, profile) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) if unavailable(&e) => Ok(None),
+            Err(e) => Err(keyring_error(profile, e)),
+        }
+    }
+
+    fn remove_entry(&self, profile: &str) -> Result<bool, ConfigError> {
+        let Some(entry) = self.entry(profile)? else {
+            return Ok(false);
+        };
+        match entry.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) if unavailable(&e) => Ok(false),
+            Err(e) => Err(keyring_error(profile, e)),
+        }
+    }
+
+    fn remove_file(&self) -> Result<(), ConfigError> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(self.error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_file(&self) -> Result<BTreeMap<String, StoredTokens>, ConfigError> {
+        let data = fs::read(&self.path).map_err(|e| self.error(e))?;
+        let body = data
+            .strip_prefix(MAGIC)
+            .filter(|body| body.len() > SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| self.error("not a credentials file"))?;
+        let (salt, body) = body.split_at(SALT_LEN);
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+
+        let key = derive_key(self.passphrase(false)?, salt).map_err(|e| self.error(e))?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()));
+        let plain = cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map(Zeroizing::new)
+            .map_err(|_| self.error("wrong passphrase or damaged file"))?;
+        let parsed: BTreeMap<String, PlainTokens> =
+            serde_json::from_slice(&plain).map_err(|e| self.error(e))?;
+
+        Ok(parsed
+            .into_iter()
+            .map(|(profile, plain)| (profile, plain.into()))
+            .collect())
+    }
+
+    fn write_file(&self, all: &BTreeMap<String, StoredTokens>) -> Result<(), ConfigError> {
+        let plain: BTreeMap<&str, PlainTokens> = all
+            .iter()
+            .filter(|(_, tokens)| !tokens.is_empty())
+            .map(|(profile, tokens)| (profile.as_str(), tokens.into()))
+            .collect();
+        let json = serde_json::to_vec(&plain)
+            .map(Zeroizing::new)
+            .map_err(|e| self.error(e))?;
+        drop(plain);
+
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let passphrase = self.passphrase(!self.path.exists())?;
+        let key = derive_key(passphrase, &salt).map_err(|e| self.error(e))?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, json.as_slice())
+            .map_err(|_| self.error("encryption failed"))?;
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        write_private(&self.path, &data).map_err(|e| self.error(e))
+    }
+
+    /// The file's passphrase, from `VEZA_CREDENTIALS_PASSPHRASE` or asked
+    /// once per run; `confirm` asks twice, for a new file.
+    fn passphrase(&self, confirm: bool) -> Result<&SecretString, ConfigError> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = match env::var(PASSPHRASE_VAR) {
+            Ok(value) if !value.is_empty() => SecretString::from(value),
+            _ if io::stdin().is_terminal() => {
+                let ask = |prompt: &str| rpassword::prompt_password(prompt).map(SecretString::from);
+                let passphrase = ask("Credentials file passphrase: ").map_err(|e| self.error(e))?;
+                if confirm
+                    && ask("Repeat the passphrase: ")
+                        .map_err(|e| self.error(e))?
+                        .expose_secret()
+                        != passphrase.expose_secret()
+                {
+                    return Err(self.error("the passphrases do not match"));
+                }
+                passphrase
+            }
+            _ => {
+                return Err(self.error(format!(
+                    "no OS keyring available; set {} to use the encrypted file",
+                    PASSPHRASE_VAR
+                )));
+            }
+        };
+        if passphrase.expose_secret().is_empty() {
+            return Err(self.error("the passphrase cannot be empty"));
+        }
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+
+    fn error(&self, e: impl std::fmt::Display) -> ConfigError {
+        ConfigError::File(self.path.clone(), e.to_string())
+    }
+}
+
+/// Whether the keyring error means there is no keyring to use, as opposed
+/// to a keyring that failed.
+fn unavailable(error: &keyring::Error) -> bool {
+    matches!(
+        error,
+        keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_)
+    )
+}
+
+fn keyring_error(profile: &str, e: impl std::fmt::Display) -> ConfigError {
+    ConfigError::InvalidValue(
+        "keyring",
+        format!("entry '{}' of {}: {}", profile, SERVICE, e),
+    )
+}
+
+/// 32-byte file key from `passphrase` and `salt` with Argon2id.
+fn derive_key(passphrase: &SecretString, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.expose_secret().as_bytes(), salt, key.as_mut())
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+/// Replaces `path` with `data`, readable by the owner only. The data goes to
+/// a new file that is renamed over `path`, so the permissions always apply
+/// and an interrupted write leaves the old file intact.
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(dir)?;
+    }
+
+    let temporary = path.with_extension("tmp");
+    let _ = fs::remove_file(&temporary);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_store(dir: &Path, passphrase: &str) -> CredentialStore {
+        CredentialStore {
+            path: dir.join("credentials"),
+            keyring: false,
+            passphrase: OnceCell::from(SecretString::from(passphrase)),
+        }
+    }
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("veza-credentials-{}", std::process::id()));
+        let store = file_store(&dir, "correct horse");
+        let tokens = StoredTokens {
+            api_token: Some("api-secret".into()),
+            mapbox_token: None,
+        };
+
+        assert_eq!(store.save("prod", &tokens).unwrap(), Location::File);
+        let raw = fs::read(store.path()).unwrap();
+        let loaded = store.load("prod").unwrap();
+        let wrong = file_store(&dir, "wrong horse").load("prod");
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(store.path()).unwrap().permissions().mode() & 0o777
+        };
+        assert!(store.remove("prod").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!raw.windows(10).any(|w| w == b"api-secret"));
+        assert_eq!(
+            loaded.api_token.as_ref().unwrap().expose_secret(),
+            "api-secret"
+        );
+        assert!(loaded.mapbox_token.is_none());
+        assert!(!format!("{:?}", loaded).contains("api-secret"));
+        assert!(wrong.is_err());
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+    }
+}
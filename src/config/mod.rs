@@ -1,11 +1,45 @@
 use secrecy::SecretString;
-use std::env;
+use std::{cell::OnceCell, env, fmt, path::PathBuf};
 use tracing::{info, warn};
 
-#[derive(Debug)]
+use crate::utils::{generate_id::StopIdTemplate, geo::BoundingBox};
+
+pub mod credentials;
+pub mod profile;
+
+use crate::utils::redact::register_secret;
+use credentials::{CredentialStore, DEFAULT_PROFILE, StoredTokens};
+use profile::{ConfigFile, Profile, config_dir, config_path};
+
+/// Looks up an environment variable.
+type Vars = Box<dyn Fn(&str) -> Option<String>>;
+
+/// Settings of the selected profile. Everything is resolved when a command
+/// first asks for it, so commands that never call out never read the
+/// stored credentials, and those without defaults not even the config file.
 pub struct Config {
-    pub backend_api_setting: BackendApiSetting,
-    pub map_box_client_setting: MapBoxClientSetting,
+    /// Profile named on the command line.
+    requested_profile: Option<String>,
+    /// The selected profile with the name its credentials are stored under.
+    profile: OnceCell<Result<(String, Profile), ConfigError>>,
+    var: Vars,
+    stored: OnceCell<Result<StoredTokens, ConfigError>>,
+    /// The backend settings, or why they are missing.
+    backend_api_setting: OnceCell<Result<BackendApiSetting, ConfigError>>,
+    /// The Mapbox settings, or why they are missing.
+    map_box_client_setting: OnceCell<Result<MapBoxClientSetting, ConfigError>>,
+    defaults: OnceCell<Result<Defaults, ConfigError>>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("requested_profile", &self.requested_profile)
+            .field("backend_api_setting", &self.backend_api_setting)
+            .field("map_box_client_setting", &self.map_box_client_setting)
+            .field("defaults", &self.defaults)
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug)]
@@ -20,65 +54,491 @@ pub struct BackendApiSetting {
     pub api_token: SecretString,
 }
 
+/// Values a profile supplies for command options left out.
+#[derive(Debug, Default)]
+pub struct Defaults {
+    pub organization_id: Option<String>,
+    pub stop_id_pattern: Option<StopIdTemplate>,
+    pub bbox: Option<BoundingBox>,
+}
+
+/// Where a token was found, as reported by `auth status`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenSource {
+    Env(String),
+    /// Stored with `auth login`.
+    Stored,
+    ProfileFile,
+    Missing,
+}
+
+impl std::fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenSource::Env(key) => write!(f, "environment variable {}", key),
+            TokenSource::Stored => write!(f, "stored credentials"),
+            TokenSource::ProfileFile => write!(f, "config file (plain text)"),
+            TokenSource::Missing => write!(f, "not set"),
+        }
+    }
+}
+
+/// Finds a token: its own variable, else the variable the profile points
+/// at, else the stored credentials, else the token written in the profile.
+pub fn find_token(
+    key: &str,
+    env_ref: &Option<String>,
+    stored: &Option<SecretString>,
+    literal: &Option<SecretString>,
+    var: &impl Fn(&str) -> Option<String>,
+) -> (TokenSource, Option<SecretString>) {
+    if let Some(value) = var(key) {
+        return (TokenSource::Env(key.to_string()), Some(value.into()));
+    }
+    if let Some(name) = env_ref
+        && let Some(value) = var(name)
+    {
+        return (TokenSource::Env(name.clone()), Some(value.into()));
+    }
+    match (stored, literal) {
+        (Some(token), _) => (TokenSource::Stored, Some(token.clone())),
+        (None, Some(token)) => (TokenSource::ProfileFile, Some(token.clone())),
+        (None, None) => (TokenSource::Missing, None),
+    }
+}
+
+/// The profile named on the command line, by `VEZA_PROFILE` or as the
+/// file's default, with the name credentials are stored under.
+pub fn select_profile(profile: Option<&str>) -> Result<(String, Option<Profile>), ConfigError> {
+    let env_profile = env::var("VEZA_PROFILE")
+        .ok()
+        .filter(|p| !p.trim().is_empty());
+    let name = profile.or(env_profile.as_deref());
+    let Some(path) = config_path() else {
+        return match name {
+            Some(_) => Err(ConfigError::InvalidValue(
+                "--profile",
+                "no config file location; set VEZA_CONFIG".to_string(),
+            )),
+            None => Ok((DEFAULT_PROFILE.to_string(), None)),
+        };
+    };
+    let file = ConfigFile::read(&path)?;
+    let name = name.or(file.default_profile.as_deref());
+    let selected = file.select(name, &path)?;
+    Ok((name.unwrap_or(DEFAULT_PROFILE).to_string(), selected))
+}
+
+/// An environment variable, with blank values treated as unset.
+pub fn env_value(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// The credentials store: the OS keyring, with a file next to the config
+/// file as fallback.
+pub fn credential_store() -> Result<CredentialStore, ConfigError> {
+    let dir = config_dir().ok_or(ConfigError::InvalidValue(
+        "credentials",
+        "no config directory; set VEZA_CONFIG".to_string(),
+    ))?;
+    Ok(CredentialStore::new(&dir))
+}
+
 impl Config {
-    pub fn from_env() -> Result<Self, ConfigError> {
+    /// Loads `.env` and remembers `profile`. The config file profile named
+    /// `profile` (or `VEZA_PROFILE`, or the file's default profile) is read
+    /// on first use, and tokens are looked up in the environment, then the
+    /// credentials stored for the profile, then the profile itself.
+    pub fn load(profile: Option<&str>) -> Self {
         // Load .env file if present, log if it fails
         if let Err(e) = dotenv::dotenv() {
             warn!("Failed to load .env file: {}", e);
         }
+        Self::new(profile.map(str::to_string), Box::new(env_value))
+    }
 
-        let api_url = env::var("API_URL").map_err(|e| ConfigError::MissingEnvVar("API_URL", e))?;
-        let api_token =
-            env::var("API_TOKEN").map_err(|e| ConfigError::MissingEnvVar("API_TOKEN", e))?;
-        let map_box_url = env::var("MAP_BOX_URL")
-            .or_else(|_| Ok("https://api.mapbox.com".to_string())) // Default value
-            .map_err(|e| ConfigError::MissingEnvVar("MAP_BOX_URL", e))?;
-        let map_box_token = env::var("MAP_BOX_TOKEN")
-            .map_err(|e| ConfigError::MissingEnvVar("MAP_BOX_TOKEN", e))?;
-
-        // Basic validation
-        if api_url.trim().is_empty() {
-            return Err(ConfigError::InvalidValue("API_URL", "URL cannot be empty"));
+    /// Builds the config from `profile` with the `stored` tokens already
+    /// read, and the variables `var` looks up, variables winning.
+    pub fn resolve(
+        profile: Option<&Profile>,
+        stored: &StoredTokens,
+        var: impl Fn(&str) -> Option<String> + 'static,
+    ) -> Result<Self, ConfigError> {
+        let config = Self::new(None, Box::new(var));
+        let profile = profile.cloned().unwrap_or_default();
+        let _ = config
+            .profile
+            .set(Ok((DEFAULT_PROFILE.to_string(), profile)));
+        let _ = config.stored.set(Ok(stored.clone()));
+        config.defaults()?;
+        Ok(config)
+    }
+
+    fn new(requested_profile: Option<String>, var: Vars) -> Self {
+        Config {
+            requested_profile,
+            profile: OnceCell::new(),
+            var,
+            stored: OnceCell::new(),
+            backend_api_setting: OnceCell::new(),
+            map_box_client_setting: OnceCell::new(),
+            defaults: OnceCell::new(),
         }
-        if map_box_url.trim().is_empty() {
+    }
+
+    /// The selected profile and the name its credentials are stored under.
+    fn profile(&self) -> Result<&(String, Profile), ConfigError> {
+        self.profile
+            .get_or_init(|| {
+                let (name, selected) = select_profile(self.requested_profile.as_deref())?;
+                if selected.is_some() {
+                    info!("Using profile '{}'", name);
+                }
+                Ok((name, selected.unwrap_or_default()))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Values the profile supplies for options a command was not given.
+    pub fn defaults(&self) -> Result<&Defaults, ConfigError> {
+        self.defaults
+            .get_or_init(|| {
+                let (_, profile) = self.profile()?;
+                let stop_id_pattern = profile
+                    .stop_id_pattern
+                    .as_deref()
+                    .map(StopIdTemplate::parse)
+                    .transpose()
+                    .map_err(|e| ConfigError::InvalidValue("stop_id_pattern", e))?;
+                let bbox = profile
+                    .bbox
+                    .as_deref()
+                    .map(str::parse::<BoundingBox>)
+                    .transpose()
+                    .map_err(|e| ConfigError::InvalidValue("bbox", e))?;
+                Ok(Defaults {
+                    organization_id: profile.organization.clone(),
+                    stop_id_pattern,
+                    bbox,
+                })
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Backend settings, for commands that talk to the API.
+    pub fn backend(&self) -> Result<&BackendApiSetting, ConfigError> {
+        self.backend_api_setting
+            .get_or_init(|| {
+                let backend = self.resolve_backend()?;
+                register_secret(&backend.api_token);
+                Ok(backend)
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Mapbox settings, for commands that geocode.
+    pub fn mapbox(&self) -> Result<&MapBoxClientSetting, ConfigError> {
+        self.map_box_client_setting
+            .get_or_init(|| {
+                let mapbox = self.resolve_mapbox()?;
+                register_secret(&mapbox.map_api_token);
+                Ok(mapbox)
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    fn resolve_backend(&self) -> Result<BackendApiSetting, ConfigError> {
+        let (_, profile) = self.profile()?;
+        let base_url = (self.var)("API_URL")
+            .or(profile.api_url.clone())
+            .ok_or(ConfigError::Missing("API_URL"))?;
+        if base_url.trim().is_empty() {
+            return Err(ConfigError::InvalidValue(
+                "API_URL",
+                "URL cannot be empty".to_string(),
+            ));
+        }
+        let api_token = self.token(
+            "API_TOKEN",
+            &profile.api_token_env,
+            |stored| &stored.api_token,
+            &profile.api_token,
+        )?;
+        info!("Loaded backend API base URL: {}", base_url);
+        Ok(BackendApiSetting {
+            base_url,
+            api_token,
+        })
+    }
+
+    fn resolve_mapbox(&self) -> Result<MapBoxClientSetting, ConfigError> {
+        let (_, profile) = self.profile()?;
+        let base_url = (self.var)("MAP_BOX_URL")
+            .or(profile.mapbox_url.clone())
+            .unwrap_or_else(|| "https://api.mapbox.com".to_string()); // Default value
+        if base_url.trim().is_empty() {
             return Err(ConfigError::InvalidValue(
                 "MAP_BOX_URL",
-                "URL cannot be empty",
+                "URL cannot be empty".to_string(),
             ));
         }
+        let map_api_token = self.token(
+            "MAP_BOX_TOKEN",
+            &profile.mapbox_token_env,
+            |stored| &stored.mapbox_token,
+            &profile.mapbox_token,
+        )?;
+        info!("Loaded MapBox base URL: {}", base_url);
+        Ok(MapBoxClientSetting {
+            base_url,
+            map_api_token,
+        })
+    }
+
+    /// Looks a token up as `find_token` does, reading the stored credentials
+    /// only when no variable has it. Unreadable credentials are the error
+    /// when the profile has no token either.
+    fn token(
+        &self,
+        key: &'static str,
+        env_ref: &Option<String>,
+        stored: impl Fn(&StoredTokens) -> &Option<SecretString>,
+        literal: &Option<SecretString>,
+    ) -> Result<SecretString, ConfigError> {
+        if let (_, Some(token)) = find_token(key, env_ref, &None, &None, &self.var) {
+            return Ok(token);
+        }
+        let stored = match self.stored() {
+            Ok(tokens) => stored(tokens),
+            Err(e) if literal.is_none() => return Err(e.clone()),
+            Err(_) => &None,
+        };
+        match find_token(key, env_ref, stored, literal, &self.var) {
+            (_, Some(token)) => Ok(token),
+            (_, None) => Err(match env_ref {
+                Some(name) => ConfigError::MissingEnvVar(name.clone()),
+                None => ConfigError::Missing(key),
+            }),
+        }
+    }
 
-        info!("Loaded backend API base URL: {}", api_url);
-        info!("Loaded MapBox base URL: {}", map_box_url);
-
-        Ok(Config {
-            backend_api_setting: BackendApiSetting {
-                base_url: api_url,
-                api_token: api_token.into(),
-            },
-            map_box_client_setting: MapBoxClientSetting {
-                base_url: map_box_url,
-                map_api_token: map_box_token.into(),
-            },
+    /// The tokens stored for the profile, read once.
+    fn stored(&self) -> &Result<StoredTokens, ConfigError> {
+        self.stored.get_or_init(|| {
+            let (name, _) = self.profile()?;
+            match credential_store() {
+                Ok(store) => store.load(name),
+                Err(_) => Ok(StoredTokens::default()),
+            }
         })
     }
+
+    /// A config with the given settings and no profile, for tests.
+    #[cfg(test)]
+    pub fn with_settings(
+        backend: Result<BackendApiSetting, ConfigError>,
+        mapbox: Result<MapBoxClientSetting, ConfigError>,
+    ) -> Self {
+        let config = Self::new(None, Box::new(|_| None));
+        let _ = config
+            .profile
+            .set(Ok((DEFAULT_PROFILE.to_string(), Profile::default())));
+        let _ = config.backend_api_setting.set(backend);
+        let _ = config.map_box_client_setting.set(mapbox);
+        config
+    }
 }
 
 /// Custom error type for configuration loading issues.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ConfigError {
-    MissingEnvVar(&'static str, env::VarError),
-    InvalidValue(&'static str, &'static str),
+    /// Set neither in the environment, the stored credentials nor the profile.
+    Missing(&'static str),
+    /// A profile refers to a token variable that is not set.
+    MissingEnvVar(String),
+    InvalidValue(&'static str, String),
+    File(PathBuf, String),
+    UnknownProfile(String, PathBuf),
 }
 
 impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ConfigError::MissingEnvVar(key, e) => {
-                write!(f, "Missing environment variable {}: {}", key, e)
+            ConfigError::Missing(key) => write!(
+                f,
+                "{} is not set; use `auth login`, the environment or a config profile",
+                key
+            ),
+            ConfigError::MissingEnvVar(key) => {
+                write!(f, "Missing environment variable {}", key)
             }
             ConfigError::InvalidValue(key, msg) => write!(f, "Invalid value for {}: {}", key, msg),
+            ConfigError::File(path, msg) => {
+                write!(f, "Cannot use '{}': {}", path.display(), msg)
+            }
+            ConfigError::UnknownProfile(name, path) => write!(
+                f,
+                "No profile '{}' in config file '{}'",
+                name,
+                path.display()
+            ),
         }
     }
 }
 
 impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+    use std::collections::HashMap;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn test_resolve_env_over_profile() {
+        let profile = Profile {
+            api_url: Some("https://prod.example.com".to_string()),
+            api_token_env: Some("PROD_TOKEN".to_string()),
+            mapbox_token: Some("pk.profile".into()),
+            organization: Some("acme".to_string()),
+            stop_id_pattern: Some("{ZONE}-{SEQ:04}".to_string()),
+            ..Default::default()
+        };
+
+        let config = Config::resolve(
+            Some(&profile),
+            &StoredTokens::default(),
+            vars(&[("PROD_TOKEN", "secret"), ("MAP_BOX_TOKEN", "pk.env")]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.backend().unwrap().base_url,
+            "https://prod.example.com"
+        );
+        assert_eq!(
+            config.backend().unwrap().api_token.expose_secret(),
+            "secret"
+        );
+        assert_eq!(
+            config.mapbox().unwrap().map_api_token.expose_secret(),
+            "pk.env"
+        );
+        assert_eq!(config.mapbox().unwrap().base_url, "https://api.mapbox.com");
+        let defaults = config.defaults().unwrap();
+        assert_eq!(defaults.organization_id.as_deref(), Some("acme"));
+        assert!(defaults.stop_id_pattern.is_some());
+
+        let config = Config::resolve(
+            Some(&profile),
+            &StoredTokens::default(),
+            vars(&[("API_URL", "http://localhost"), ("PROD_TOKEN", "secret")]),
+        )
+        .unwrap();
+        assert_eq!(config.backend().unwrap().base_url, "http://localhost");
+    }
+
+    #[test]
+    fn test_resolve_missing_values() {
+        let profile = Profile {
+            api_url: Some("https://prod.example.com".to_string()),
+            api_token_env: Some("PROD_TOKEN".to_string()),
+            ..Default::default()
+        };
+        let config = Config::resolve(Some(&profile), &StoredTokens::default(), vars(&[])).unwrap();
+        let error = config.backend().unwrap_err();
+        assert_eq!(error.to_string(), "Missing environment variable PROD_TOKEN");
+        let config = Config::resolve(
+            None,
+            &StoredTokens::default(),
+            vars(&[("API_URL", "http://localhost"), ("API_TOKEN", "secret")]),
+        )
+        .unwrap();
+        assert!(config.backend().is_ok());
+        assert!(matches!(
+            config.mapbox().unwrap_err(),
+            ConfigError::Missing("MAP_BOX_TOKEN")
+        ));
+        let config = Config::resolve(None, &StoredTokens::default(), vars(&[])).unwrap();
+        assert!(matches!(
+            config.backend().unwrap_err(),
+            ConfigError::Missing("API_URL")
+        ));
+    }
+
+    #[test]
+    fn test_stored_token_between_env_and_profile() {
+        let profile = Profile {
+            api_url: Some("https://prod.example.com".to_string()),
+            api_token: Some("plain".into()),
+            mapbox_token: Some("pk.plain".into()),
+            ..Default::default()
+        };
+        let stored = StoredTokens {
+            api_token: Some("stored".into()),
+            mapbox_token: None,
+        };
+
+        let config = Config::resolve(Some(&profile), &stored, vars(&[])).unwrap();
+        assert_eq!(
+            config.backend().unwrap().api_token.expose_secret(),
+            "stored"
+        );
+        assert_eq!(
+            config.mapbox().unwrap().map_api_token.expose_secret(),
+            "pk.plain"
+        );
+        assert!(!format!("{:?}", config).contains("stored"));
+
+        let (source, _) = find_token(
+            "API_TOKEN",
+            &None,
+            &stored.api_token,
+            &None,
+            &vars(&[("API_TOKEN", "env")]),
+        );
+        assert_eq!(source, TokenSource::Env("API_TOKEN".to_string()));
+    }
+
+    #[test]
+    fn test_credentials_read_only_when_needed() {
+        let mut config = Config::resolve(
+            None,
+            &StoredTokens::default(),
+            vars(&[("API_URL", "http://localhost"), ("API_TOKEN", "secret")]),
+        )
+        .unwrap();
+        let error = ConfigError::File(PathBuf::from("credentials"), "damaged".to_string());
+        config.stored = OnceCell::from(Err(error));
+
+        assert!(config.backend().is_ok());
+        assert!(matches!(
+            config.mapbox().unwrap_err(),
+            ConfigError::File(_, message) if message == "damaged"
+        ));
+
+        // With every token in the environment the store is never read
+        config.stored = OnceCell::new();
+        config.var = Box::new(vars(&[
+            ("API_URL", "http://localhost"),
+            ("API_TOKEN", "secret"),
+            ("MAP_BOX_TOKEN", "pk.env"),
+        ]));
+        config.backend_api_setting = OnceCell::new();
+        config.map_box_client_setting = OnceCell::new();
+        assert!(config.backend().is_ok());
+        assert!(config.mapbox().is_ok());
+        assert!(config.stored.get().is_none());
+    }
+}
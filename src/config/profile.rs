@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use secrecy::SecretString;
+use serde::Deserialize;
+
+use super::ConfigError;
+
+/// Settings of one named profile in the config file. Tokens are best stored
+/// with `auth login` or given as the name of an environment variable holding
+/// them (`*_token_env`) rather than written here.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub api_url: Option<String>,
+    pub api_token: Option<SecretString>,
+    pub api_token_env: Option<String>,
+    pub mapbox_url: Option<String>,
+    pub mapbox_token: Option<SecretString>,
+    pub mapbox_token_env: Option<String>,
+    /// Organization used when a command is not given one.
+    pub organization: Option<String>,
+    /// StopID pattern used when a command is not given one.
+    pub stop_id_pattern: Option<String>,
+    /// Area stops are expected in, as minLon,minLat,maxLon,maxLat. Default
+    /// for --bbox of `stop validate` and --expect-bbox of `stop format
+    /// read-xlsx`; it never filters stops out.
+    pub bbox: Option<String>,
+}
+
+/// The config file: named profiles and which one to use by default.
+///
+/// ```toml
+/// default_profile = "staging"
+///
+/// [profiles.staging]
+/// api_url = "https://staging.example.com/api/graphql"
+/// api_token_env = "STAGING_API_TOKEN"
+/// organization = "cl0x..."
+/// stop_id_pattern = "{ZONE}-{SEQ:04}"
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl ConfigFile {
+    pub fn parse(text: &str, path: &Path) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError::File(path.to_path_buf(), e.to_string()))
+    }
+
+    /// Reads the file at `path`; a missing file is an empty config.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text, path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+            Err(e) => Err(ConfigError::File(path.to_path_buf(), e.to_string())),
+        }
+    }
+
+    /// The profile named `name`, else the default profile, else none.
+    pub fn select(&self, name: Option<&str>, path: &Path) -> Result<Option<Profile>, ConfigError> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(Some(profile.clone())),
+            None => Err(ConfigError::UnknownProfile(
+                name.to_string(),
+                path.to_path_buf(),
+            )),
+        }
+    }
+}
+
+/// `VEZA_CONFIG`, else `$XDG_CONFIG_HOME/veza/config.toml`, else
+/// `~/.config/veza/config.toml`.
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = env::var("VEZA_CONFIG")
+        && !path.trim().is_empty()
+    {
+        return Some(PathBuf::from(path));
+    }
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// Directory of the config file, where credentials are stored too.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Ok(path) = env::var("VEZA_CONFIG")
+        && !path.trim().is_empty()
+    {
+        let parent = Path::new(&path).parent()?;
+        return Some(match parent.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => parent.to_path_buf(),
+        });
+    }
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.trim().is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()?.join(".config"),
+    };
+    Some(base.join("veza"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_profile() {
+        let path = Path::new("config.toml");
+        let file = ConfigFile::parse(
+            r#"
+            default_profile = "dev"
+
+            [profiles.dev]
+            api_url = "http://localhost:3000/api/graphql"
+
+            [profiles.prod]
+            api_url = "https://example.com/api/graphql"
+            api_token_env = "PROD_API_TOKEN"
+            organization = "acme"
+            "#,
+            path,
+        )
+        .unwrap();
+
+        let dev = file.select(None, path).unwrap().unwrap();
+        assert_eq!(
+            dev.api_url.as_deref(),
+            Some("http://localhost:3000/api/graphql")
+        );
+        let prod = file.select(Some("prod"), path).unwrap().unwrap();
+        assert_eq!(prod.api_token_env.as_deref(), Some("PROD_API_TOKEN"));
+        assert_eq!(prod.organization.as_deref(), Some("acme"));
+        assert!(file.select(Some("qa"), path).is_err());
+        assert!(ConfigFile::default().select(None, path).unwrap().is_none());
+        assert!(ConfigFile::parse("[profiles.dev]\napi_ur = \"x\"", path).is_err());
+    }
+}
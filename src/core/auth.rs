@@ -0,0 +1,122 @@
+use std::error::Error;
+
+use secrecy::{ExposeSecret, SecretString};
+use tracing::warn;
+
+use crate::{
+    cli::{AuthCommand, LogoutArgs},
+    config::{
+        credential_store,
+        credentials::{DEFAULT_PROFILE, Location, StoredTokens},
+        env_value, find_token,
+        profile::{ConfigFile, config_path},
+        select_profile,
+    },
+    utils::prompt::secret,
+};
+
+pub fn process_auth_command(
+    command: AuthCommand,
+    profile: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = dotenv::dotenv() {
+        warn!("Failed to load .env file: {}", e);
+    }
+    match command {
+        AuthCommand::Login => login(profile),
+        AuthCommand::Logout(args) => logout(args, profile),
+        AuthCommand::Status => status(profile),
+    }
+}
+
+fn login(profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (name, _) = select_profile(profile)?;
+    let store = credential_store()?;
+    let current = store.load(&name)?;
+
+    println!("Storing tokens for profile '{}'", name);
+    println!("Leave a token empty to keep the stored one");
+    let keep = |new: SecretString, old: Option<SecretString>| match new.expose_secret().is_empty() {
+        true => old,
+        false => Some(new),
+    };
+    let tokens = StoredTokens {
+        api_token: keep(secret("API token")?, current.api_token),
+        mapbox_token: keep(secret("Mapbox token")?, current.mapbox_token),
+    };
+    if tokens.is_empty() {
+        return Err("No token given, nothing stored".into());
+    }
+
+    match store.save(&name, &tokens)? {
+        Location::Keyring => println!("Tokens saved in the OS keyring"),
+        Location::File => println!("Tokens saved to {}", store.path().display()),
+    }
+    Ok(())
+}
+
+fn logout(args: LogoutArgs, profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let store = credential_store()?;
+    if args.all {
+        // Keyring entries cannot be listed; remove those of known profiles
+        let mut profiles = vec![DEFAULT_PROFILE.to_string()];
+        if let Some(path) = config_path() {
+            profiles.extend(ConfigFile::read(&path)?.profiles.into_keys());
+        }
+        store.remove_all(&profiles)?;
+        println!("Removed the stored tokens of all profiles");
+        return Ok(());
+    }
+
+    let (name, _) = select_profile(profile)?;
+    match store.remove(&name)? {
+        true => println!("Removed the stored tokens of profile '{}'", name),
+        false => println!("No tokens stored for profile '{}'", name),
+    }
+    Ok(())
+}
+
+fn status(profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (name, selected) = select_profile(profile)?;
+    let store = credential_store()?;
+    let (location, stored) = store.find(&name)?.unzip();
+    let stored = stored.unwrap_or_default();
+    let profile = selected.unwrap_or_default();
+
+    println!("Profile: {}", name);
+    if let Some(path) = config_path() {
+        let state = if path.exists() { "" } else { " (not found)" };
+        println!("Config file: {}{}", path.display(), state);
+    }
+    match location {
+        Some(Location::Keyring) => println!("Stored credentials: OS keyring"),
+        Some(Location::File) => println!("Stored credentials: {}", store.path().display()),
+        None => println!("Stored credentials: none"),
+    }
+    let tokens = [
+        (
+            "API token",
+            find_token(
+                "API_TOKEN",
+                &profile.api_token_env,
+                &stored.api_token,
+                &profile.api_token,
+                &env_value,
+            ),
+        ),
+        (
+            "Mapbox token",
+            find_token(
+                "MAP_BOX_TOKEN",
+                &profile.mapbox_token_env,
+                &stored.mapbox_token,
+                &profile.mapbox_token,
+                &env_value,
+            ),
+        ),
+    ];
+    for (label, (source, _)) in tokens {
+        println!("{}: {}", label, source);
+    }
+    Ok(())
+}
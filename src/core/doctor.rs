@@ -0,0 +1,311 @@
+use std::error::Error;
+
+use reqwest::{Client, StatusCode};
+use tracing::warn;
+
+use crate::{
+    config::{
+        Config, ConfigError, credential_store,
+        credentials::{Location, StoredTokens},
+        env_value, find_token,
+        profile::{Profile, config_path},
+        select_profile,
+    },
+    query::stop_query::count_stops,
+    service::{geocoding_service::GeocodingService, graphql::GraphQLService},
+};
+
+/// Result of one doctor check.
+enum Check {
+    Pass(String),
+    Fail {
+        problem: String,
+        fix: String,
+    },
+    /// Not run because a setting it needs is missing.
+    Skip(String),
+}
+
+impl Check {
+    fn fail(problem: impl ToString, fix: impl ToString) -> Self {
+        Check::Fail {
+            problem: problem.to_string(),
+            fix: fix.to_string(),
+        }
+    }
+}
+
+/// Prints the checks as they run and counts the failures.
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn show(&mut self, name: &str, check: Check) {
+        match check {
+            Check::Pass(detail) => println!("ok    {:<20} {}", name, detail),
+            Check::Skip(reason) => println!("skip  {:<20} {}", name, reason),
+            Check::Fail { problem, fix } => {
+                self.failures += 1;
+                println!("FAIL  {:<20} {}", name, problem);
+                println!("      {:<20} fix: {}", "", fix);
+            }
+        }
+    }
+}
+
+/// Checks the config file, profile, credentials, settings and whether the
+/// backend and Mapbox accept the tokens, with a fix for each failure.
+pub async fn process_doctor(profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = dotenv::dotenv() {
+        warn!("Failed to load .env file: {}", e);
+    }
+    let mut report = Report::default();
+
+    let check = match config_path() {
+        Some(path) if path.exists() => Check::Pass(path.display().to_string()),
+        Some(path) => Check::Pass(format!(
+            "{} not found, using the environment only",
+            path.display()
+        )),
+        None => Check::fail(
+            "no config file location",
+            "set VEZA_CONFIG to the path of the config file",
+        ),
+    };
+    report.show("Config file", check);
+
+    let (name, selected) = match select_profile(profile) {
+        Ok(selection) => selection,
+        Err(e) => {
+            let fix = fix_for(&e);
+            report.show("Profile", Check::fail(e, fix));
+            report.show(
+                "Everything else",
+                Check::Skip("needs a usable profile".into()),
+            );
+            return finish(report);
+        }
+    };
+    let check = match &selected {
+        Some(_) => Check::Pass(format!("'{}'", name)),
+        None => Check::Pass("none, using the environment only".to_string()),
+    };
+    report.show("Profile", check);
+
+    let stored = match credential_store().and_then(|store| Ok((store.find(&name)?, store))) {
+        Ok((found, store)) => {
+            let detail = match &found {
+                Some((Location::Keyring, _)) => format!("in the OS keyring for '{}'", name),
+                Some((Location::File, _)) => {
+                    format!("in {} for '{}'", store.path().display(), name)
+                }
+                None => format!("nothing stored for '{}'", name),
+            };
+            report.show("Credentials", Check::Pass(detail));
+            found.map(|(_, tokens)| tokens).unwrap_or_default()
+        }
+        Err(e) => {
+            let fix = fix_for(&e);
+            report.show("Credentials", Check::fail(e, fix));
+            StoredTokens::default()
+        }
+    };
+
+    let config = match Config::resolve(selected.as_ref(), &stored, env_value) {
+        Ok(config) => config,
+        Err(e) => {
+            let fix = fix_for(&e);
+            report.show("Profile defaults", Check::fail(e, fix));
+            report.show(
+                "Everything else",
+                Check::Skip("needs a valid profile".into()),
+            );
+            return finish(report);
+        }
+    };
+    let profile = selected.unwrap_or_default();
+
+    report.show(
+        "Backend settings",
+        backend_settings(&config, &profile, &stored),
+    );
+    report.show("Backend connection", check_backend(&config).await);
+    report.show(
+        "Mapbox settings",
+        mapbox_settings(&config, &profile, &stored),
+    );
+    report.show("Mapbox token", check_geocoder(&config).await);
+
+    finish(report)
+}
+
+fn finish(report: Report) -> Result<(), Box<dyn Error>> {
+    match report.failures {
+        0 => {
+            println!("All checks passed");
+            Ok(())
+        }
+        1 => Err("1 check failed".into()),
+        n => Err(format!("{} checks failed", n).into()),
+    }
+}
+
+fn backend_settings(config: &Config, profile: &Profile, stored: &StoredTokens) -> Check {
+    match config.backend() {
+        Ok(backend) => {
+            let (source, _) = find_token(
+                "API_TOKEN",
+                &profile.api_token_env,
+                &stored.api_token,
+                &profile.api_token,
+                &env_value,
+            );
+            Check::Pass(format!("{}, token from {}", backend.base_url, source))
+        }
+        Err(e) => Check::fail(&e, fix_for(&e)),
+    }
+}
+
+fn mapbox_settings(config: &Config, profile: &Profile, stored: &StoredTokens) -> Check {
+    match config.mapbox() {
+        Ok(mapbox) => {
+            let (source, _) = find_token(
+                "MAP_BOX_TOKEN",
+                &profile.mapbox_token_env,
+                &stored.mapbox_token,
+                &profile.mapbox_token,
+                &env_value,
+            );
+            Check::Pass(format!("{}, token from {}", mapbox.base_url, source))
+        }
+        Err(e) => Check::fail(&e, fix_for(&e)),
+    }
+}
+
+/// Counts stops, which needs both a reachable backend and a valid token.
+async fn check_backend(config: &Config) -> Check {
+    let Ok(service) = GraphQLService::new(config) else {
+        return Check::Skip("backend settings are missing".to_string());
+    };
+    match count_stops(&service).await {
+        Ok(count) => Check::Pass(format!("authenticated, {} stops visible", count)),
+        Err(e) => Check::fail(
+            e,
+            "check that API_URL is the GraphQL endpoint and the API token is valid \
+             (`veza auth login` stores a new one)",
+        ),
+    }
+}
+
+/// Sends one forward geocoding query with the token.
+async fn check_geocoder(config: &Config) -> Check {
+    if config.mapbox().is_err() {
+        return Check::Skip("Mapbox settings are missing".to_string());
+    }
+    let service = GeocodingService::new(Client::new(), config);
+    match service.check_token().await {
+        Ok(StatusCode::OK) => Check::Pass("accepted".to_string()),
+        Ok(StatusCode::UNAUTHORIZED) => Check::fail(
+            "token rejected (401)",
+            "copy a valid public token from your Mapbox account and run `veza auth login`",
+        ),
+        Ok(StatusCode::FORBIDDEN) => Check::fail(
+            "token not allowed to geocode (403)",
+            "use a token without URL restrictions that has geocoding access",
+        ),
+        Ok(status) => Check::fail(
+            format!("unexpected status {}", status),
+            "check that MAP_BOX_URL points at the Mapbox API",
+        ),
+        Err(e) => Check::fail(
+            format!("not reachable: {}", e),
+            "check MAP_BOX_URL and the network connection",
+        ),
+    }
+}
+
+/// What to do about a config problem.
+fn fix_for(error: &ConfigError) -> String {
+    match error {
+        ConfigError::Missing("API_URL") => {
+            "set API_URL or api_url in the config profile".to_string()
+        }
+        ConfigError::Missing("API_TOKEN") => "run `veza auth login` or set API_TOKEN".to_string(),
+        ConfigError::Missing("MAP_BOX_TOKEN") => {
+            "run `veza auth login` or set MAP_BOX_TOKEN; only geocoding needs it".to_string()
+        }
+        ConfigError::Missing(key) => format!("set {}", key),
+        ConfigError::MissingEnvVar(key) => format!(
+            "export {} or remove the *_token_env entry naming it from the profile",
+            key
+        ),
+        ConfigError::InvalidValue("keyring", _) => {
+            "unlock the OS keyring, or reset the entry with `veza auth logout`".to_string()
+        }
+        ConfigError::InvalidValue(key, _) => {
+            format!("correct {} in the environment or profile", key)
+        }
+        ConfigError::File(..) => {
+            "fix or remove the file; unknown keys are rejected. Without an OS keyring the \
+             credentials file needs its passphrase (VEZA_CREDENTIALS_PASSPHRASE), and \
+             `veza auth logout --all` resets it"
+                .to_string()
+        }
+        ConfigError::UnknownProfile(name, path) => format!(
+            "add [profiles.{}] to {} or pick another with --profile",
+            name,
+            path.display()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendApiSetting, MapBoxClientSetting};
+
+    #[tokio::test]
+    async fn test_checks_against_stand_in_servers() {
+        let mut backend = mockito::Server::new_async().await;
+        let backend_mock = backend
+            .mock("POST", "/")
+            .match_header("authorization", "Bearer test_token")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"data": {"stopsCount": 3}}"#)
+            .create();
+        let mut mapbox = mockito::Server::new_async().await;
+        let mapbox_mock = mapbox
+            .mock("GET", "/search/geocode/v6/forward")
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .create();
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
+                base_url: backend.url(),
+                api_token: "test_token".into(),
+            }),
+            Ok(MapBoxClientSetting {
+                base_url: mapbox.url(),
+                map_api_token: "pk.bad".into(),
+            }),
+        );
+
+        let backend_check = check_backend(&config).await;
+        let mapbox_check = check_geocoder(&config).await;
+
+        assert!(matches!(backend_check, Check::Pass(detail) if detail.contains("3 stops")));
+        assert!(matches!(mapbox_check, Check::Fail { fix, .. } if fix.contains("auth login")));
+        backend_mock.assert();
+        mapbox_mock.assert();
+
+        let config = Config::with_settings(
+            Err(ConfigError::Missing("API_URL")),
+            Err(ConfigError::Missing("MAP_BOX_TOKEN")),
+        );
+        assert!(matches!(check_backend(&config).await, Check::Skip(_)));
+        assert!(matches!(check_geocoder(&config).await, Check::Skip(_)));
+    }
+}
@@ -1,21 +1,58 @@
+pub mod auth;
+pub mod doctor;
 pub mod stop;
+pub mod stop_cluster;
+pub mod stop_create;
+pub mod stop_dedupe;
+pub mod stop_delete;
+pub mod stop_id;
+pub mod stop_import;
+pub mod stop_order;
+pub mod stop_validate;
+pub mod stop_zone;
 use std::error::Error;
 
+use auth::process_auth_command;
+use doctor::process_doctor;
 use stop::{process_export_stops_to_excel, process_format_command};
+use stop_cluster::process_cluster;
+use stop_create::process_create;
+use stop_dedupe::process_dedupe_command;
+use stop_delete::process_delete;
+use stop_id::process_id_command;
+use stop_import::process_import;
+use stop_order::process_order;
+use stop_validate::process_validate;
+use stop_zone::process_zone;
 
 use crate::cli::{Cli, ModelCommand, StopCommand};
 use crate::config::Config;
 
-pub async fn run(cli: Cli, config: &Config) -> Result<(), Box<dyn Error>> {
+pub async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
     match cli.model {
-        ModelCommand::Stop(cmd) => match cmd {
-            StopCommand::Export(args) => {
-                process_export_stops_to_excel(args.file_name, config).await?;
-            }
-            StopCommand::Format(format_command) => {
-                process_format_command(format_command, config).await?
-            }
-        },
+        ModelCommand::Auth(command) => process_auth_command(command, cli.profile.as_deref())?,
+        ModelCommand::Doctor => process_doctor(cli.profile.as_deref()).await?,
+        // Nothing is read from the profile or credentials until a command asks
+        ModelCommand::Stop(cmd) => run_stop(*cmd, &Config::load(cli.profile.as_deref())).await?,
+    }
+    Ok(())
+}
+
+async fn run_stop(cmd: StopCommand, config: &Config) -> Result<(), Box<dyn Error>> {
+    match cmd {
+        StopCommand::Export(args) => process_export_stops_to_excel(args, config).await?,
+        StopCommand::Format(format_command) => {
+            process_format_command(format_command, config).await?
+        }
+        StopCommand::Import(args) => process_import(args, config).await?,
+        StopCommand::Create(args) => process_create(args, config).await?,
+        StopCommand::Delete(args) => process_delete(args, config).await?,
+        StopCommand::Validate(args) => process_validate(args, config).await?,
+        StopCommand::Dedupe(command) => process_dedupe_command(command, config).await?,
+        StopCommand::Zone(args) => process_zone(args, config).await?,
+        StopCommand::Cluster(args) => process_cluster(args, config).await?,
+        StopCommand::Order(args) => process_order(args, config).await?,
+        StopCommand::Id(command) => process_id_command(command)?,
     }
     Ok(())
 }
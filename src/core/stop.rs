@@ -1,59 +1,92 @@
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
 use tracing::info;
 
 use crate::{
-    cli::{FormatCommand, StopIDArgs},
-    models::stop::Stop,
-    mutation::stop_mutation,
-    query::{MutationArgs, MutationsData},
-    service::geocoding_service::GeocodingService,
-    utils::{generate_id::generate_stop_id, xlsx::read_xlsx},
+    cli::{ExportArgs, FormatCommand, ReadXlsxFormatArgs, SpatialFilterArgs, StopSourceArgs},
+    core::stop_id::format_stop_id,
+    core::stop_import::update_in_batches,
+    models::stop::{AddressComponents, GeocodeStatus, Stop},
+    mutation::stop_mutation::StopWhereUnique,
+    query::MutationsData,
+    service::{
+        gazetteer::Gazetteer, geocoding_service::GeocodingService, road_snapper::RoadSnapper,
+    },
+    utils::{
+        address::AddressNormalizer,
+        geo::{
+            BoundingBox, SpatialFilter, is_valid_coordinate, looks_swapped, swap_matches_reference,
+        },
+        geojson::read_areas,
+        prompt::confirm,
+        xlsx::{SheetItems, read_xlsx, read_xlsx_sheets, write_back_xlsx},
+    },
 };
 use std::error::Error;
 
 use crate::{
     config::Config,
-    query::{Cursor, QueryArgs, stop_query::fetch_stops},
+    query::{
+        QueryArgs,
+        stop_query::{fetch_all_stops, fetch_stops_matching},
+    },
     service::graphql::GraphQLService,
     utils::xlsx::write_xlsx,
 };
 
 pub async fn process_export_stops_to_excel(
-    file_name: String,
+    args: ExportArgs,
     config: &Config,
 ) -> Result<(), Box<dyn Error>> {
-    let service = GraphQLService::new(config);
-    let mut args = QueryArgs::default();
-    let mut all_stops: Vec<crate::models::stop::Stop> = Vec::new();
-
-    loop {
-        let response: crate::query::GraphQLResponse<crate::query::stop_query::StopResponse> =
-            fetch_stops(&args, &service).await?;
-
-        // Check if data exists
-        if let Some(stop_response) = response.data {
-            let count = stop_response.stops.len();
-            all_stops.extend(stop_response.stops);
-
-            // If we received fewer results than `take`, we are done
-            if count < args.take.unwrap_or(250) as usize {
-                break;
-            }
-            args.skip = Some(1);
-            args.cursor = Some(Cursor {
-                id: all_stops.last().unwrap().id.clone(),
-            })
-        } else {
-            break;
-        }
+    let filter = spatial_filter(&args.spatial_args)?;
+    let service = GraphQLService::new(config)?;
+    let all_stops = fetch_stops_matching(QueryArgs::default(), &service, |stop| {
+        filter.matches(stop.coordinates())
+    })
+    .await?;
+    if !filter.is_empty() {
+        info!("{} stops match the spatial filter", all_stops.len());
     }
 
-    write_xlsx(all_stops, &file_name)?;
+    write_xlsx(all_stops, &args.file_name)?;
     Ok(())
 }
 
+/// The client-side filter of `args`, reading the --within polygons.
+pub fn spatial_filter(args: &SpatialFilterArgs) -> Result<SpatialFilter, Box<dyn Error>> {
+    Ok(SpatialFilter {
+        bbox: args.bbox,
+        near: args.near.zip(args.radius_m),
+        within: match &args.within {
+            Some(path) => read_areas(path)?,
+            None => Vec::new(),
+        },
+    })
+}
+
+/// Loads stops from the spreadsheet or backend selected by `source`.
+pub async fn load_stops(
+    source: &StopSourceArgs,
+    config: &Config,
+) -> Result<Vec<Stop>, Box<dyn Error>> {
+    match &source.file_path {
+        Some(file_path) => read_xlsx(file_path, &source.sheet_args.read_options()),
+        None => {
+            let service = GraphQLService::new(config)?;
+            let organization_id = source
+                .organization_id
+                .as_ref()
+                .or(config.defaults()?.organization_id.as_ref());
+            let args = match organization_id {
+                Some(organization_id) => QueryArgs::default().with_organization(organization_id),
+                None => QueryArgs::default(),
+            };
+            let stops = fetch_all_stops(args, &service).await?;
+            info!("Fetched {} stops from the backend", stops.len());
+            Ok(stops)
+        }
+    }
+}
+
 pub async fn process_format_command(
     command: FormatCommand,
     config: &Config,
@@ -65,15 +98,7 @@ pub async fn process_format_command(
 
         FormatCommand::ReadXlsx(args) => match args.update_backend {
             true => {}
-            false => {
-                let mut stops: Vec<Stop> = read_xlsx(&args.file_path)?;
-                info!("Read {} stops from {}", stops.len(), args.file_path);
-                let client = Client::new();
-                let geocoding_service = GeocodingService::new(client, config);
-                geocoding_service.geocode_stops(&mut stops).await?;
-                info!("Writing formatted stops to {}", args.output_file);
-                write_xlsx(stops, &args.output_file)?;
-            }
+            false => format_xlsx_stops(*args, config).await?,
         },
 
         FormatCommand::StopID(args) => format_stop_id(args, config).await?,
@@ -81,78 +106,288 @@ pub async fn process_format_command(
     Ok(())
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct StopData {
-    #[serde(rename = "stopId")]
-    stop_id: String,
-}
+/// How far the swapped source point may be from the geocoded address for the
+/// geocoder to count as confirming the swap.
+const SWAP_MATCH_TOLERANCE_M: f64 = 1_000.0;
 
-#[derive(Deserialize, Serialize, Debug)]
-struct StopWhereAge {
-    id: String,
-}
+async fn format_xlsx_stops(
+    args: ReadXlsxFormatArgs,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let filter = spatial_filter(&args.spatial_args)?;
+    let read_options = args.sheet_args.read_options();
+    let mut sheets: Vec<SheetItems<Stop>> = read_xlsx_sheets(&args.file_path, &read_options)?;
+    let mut stops: Vec<Stop> = sheets
+        .iter_mut()
+        .flat_map(|sheet| sheet.rows.iter_mut().map(|(_, stop)| std::mem::take(stop)))
+        .collect();
+    info!("Read {} stops from {}", stops.len(), args.file_path);
 
-type StopUpdateData = MutationArgs<StopData, StopWhereAge>;
-async fn format_stop_id(stop_args: StopIDArgs, config: &Config) -> Result<(), Box<dyn Error>> {
-    let service = GraphQLService::new(config);
-    let mut args = QueryArgs::default();
-
-    args.wheres.insert(
-        "organizations".to_string(),
-        json!({
-                "some": {
-                    "id": {
-                        "equals": stop_args.organization_id
-                    }
-                }
-            }
+    // Stops fixed up front keep their (swapped) source point and are not geocoded
+    let skip_geocoding = match args.fix_swapped {
+        true => fix_swapped_coordinates(
+            &mut stops,
+            args.expect_bbox.or(config.defaults()?.bbox).as_ref(),
         ),
-    );
-
-    let mut index = 0;
+        false => vec![false; stops.len()],
+    };
 
-    loop {
-        let response: crate::query::GraphQLResponse<crate::query::stop_query::StopResponse> =
-            fetch_stops(&args, &service).await?;
-
-        let mut update_stops: StopUpdateData = StopUpdateData { data: vec![] };
+    // Filter before geocoding, so stops left out cost no requests
+    let (mut stops, mut skip_geocoding) =
+        retain_in_filter(&filter, &mut sheets, stops, skip_geocoding);
+    if !filter.is_empty() {
+        info!("{} stops match the spatial filter", stops.len());
+    }
 
-        // Check if data exists
-        if let Some(stop_response) = response.data {
-            let count = stop_response.stops.len();
+    let normalizer = args.normalize_address.then(|| {
+        let mut normalizer = AddressNormalizer {
+            suffix: args.address_suffix.clone(),
+            ..Default::default()
+        };
+        for (abbreviation, expansion) in args.abbreviations.iter() {
+            normalizer
+                .abbreviations
+                .insert(abbreviation.to_lowercase(), expansion.clone());
+        }
+        normalizer
+    });
+    for (stop, skip) in stops.iter_mut().zip(skip_geocoding.iter_mut()) {
+        stop.original_position = stop.position.clone();
+        let Some(normalizer) = &normalizer else {
+            continue;
+        };
+        stop.geocode_query = normalizer.clean(&stop.position);
+        if stop.geocode_query.is_empty() && !*skip {
+            *skip = true;
+            stop.geocode_status = GeocodeStatus::Failed;
+            stop.flags.push("no address left after cleanup".to_string());
+        }
+    }
+    let source_points: Vec<Option<(f64, f64)>> = stops.iter().map(Stop::coordinates).collect();
 
-            let stops: Vec<Stop> = stop_response.stops;
+    let client = Client::new();
+    let mut geocoding_service = GeocodingService::new(client, config).with_batch(args.batch);
+    if let Some(path) = &args.gazetteer {
+        let gazetteer = Gazetteer::from_file(path, args.gazetteer_min_similarity)?;
+        geocoding_service = geocoding_service.with_gazetteer(gazetteer);
+    }
+    geocode_selected(&geocoding_service, &mut stops, &skip_geocoding).await?;
 
-            for stop in stops.iter() {
-                let id = generate_stop_id(&stop_args.pattern, index);
-                index += 1;
-                update_stops.data.push(MutationsData {
-                    data: StopData { stop_id: id },
-                    wheres: StopWhereAge {
-                        id: stop.id.clone(),
-                    },
-                });
+    if args.fix_swapped {
+        for (stop, source_point) in stops.iter_mut().zip(source_points) {
+            if stop.geocode_status != GeocodeStatus::Geocoded {
+                continue;
             }
-            info!("Updating {}", count);
-            let s = stop_mutation::stop_mutation(update_stops, &service).await;
-            match s {
-                Ok(_) => {}
-                Err(e) => info!("error {}", e),
+            if let (Some(source_point), Some(geocoded)) = (source_point, stop.coordinates())
+                && swap_matches_reference(source_point, geocoded, SWAP_MATCH_TOLERANCE_M)
+            {
+                stop.latitude = source_point.1.to_string();
+                stop.longitude = source_point.0.to_string();
+                stop.flags
+                    .push("lat/lon swapped (confirmed by geocoded address)".to_string());
             }
+        }
+    }
 
-            info!("end update stops {}", count);
+    if let Some(path) = &args.snap_to_roads {
+        let snapper = RoadSnapper::from_pbf(path)?;
+        snapper.snap_stops(&mut stops, args.snap_max_distance);
+    }
 
-            // If we received fewer results than `take`, we are done
-            if count < args.take.unwrap_or(250) as usize {
-                break;
+    // The output is written first so a failed push loses no geocoding
+    let pushed = args.push_components.then(|| stops.clone());
+    info!("Writing formatted stops to {}", args.output_file);
+    if args.preserve_source {
+        let mut formatted = stops.into_iter();
+        for sheet in sheets.iter_mut() {
+            for (_, stop) in sheet.rows.iter_mut() {
+                *stop = formatted.next().ok_or("Formatted stop count mismatch")?;
             }
-            args.skip = Some(1);
-            args.cursor = Some(Cursor {
-                id: stops.last().unwrap().id.clone(),
-            });
-        } else {
-            break;
         }
+        write_back_xlsx(
+            &args.file_path,
+            &args.output_file,
+            &sheets,
+            &["Address", "Latitude", "Longtitude"],
+        )?;
+    } else {
+        write_xlsx(stops, &args.output_file)?;
+    }
+
+    if let Some(stops) = pushed {
+        push_address_components(&stops, &args, config).await?;
+    }
+    Ok(())
+}
+
+/// Keeps the stops `filter` matches, with their `skip` entries and sheet
+/// rows; rows left out stay as they are in the source.
+fn retain_in_filter(
+    filter: &SpatialFilter,
+    sheets: &mut [SheetItems<Stop>],
+    stops: Vec<Stop>,
+    skip: Vec<bool>,
+) -> (Vec<Stop>, Vec<bool>) {
+    let keep: Vec<bool> = stops
+        .iter()
+        .map(|stop| filter.matches(stop.coordinates()))
+        .collect();
+    let mut index = 0;
+    for sheet in sheets.iter_mut() {
+        sheet.rows.retain(|_| {
+            index += 1;
+            keep[index - 1]
+        });
+    }
+    stops
+        .into_iter()
+        .zip(skip)
+        .zip(keep)
+        .filter_map(|(stop, keep)| keep.then_some(stop))
+        .unzip()
+}
+
+/// Updates the address components of geocoded stops that exist in the backend.
+async fn push_address_components(
+    stops: &[Stop],
+    args: &ReadXlsxFormatArgs,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let updates: Vec<MutationsData<AddressComponents, StopWhereUnique>> = stops
+        .iter()
+        .filter(|stop| !stop.id.trim().is_empty() && !stop.components.is_empty())
+        .map(|stop| MutationsData {
+            data: stop.components.clone(),
+            wheres: StopWhereUnique {
+                id: stop.id.trim().to_string(),
+            },
+        })
+        .collect();
+    if updates.is_empty() {
+        println!("No geocoded stops with an ID to update");
+        return Ok(());
+    }
+    println!(
+        "{} stops to update with their address components",
+        updates.len()
+    );
+
+    if args.dry_run {
+        println!("Dry run, no changes sent");
+        return Ok(());
+    }
+    if !args.yes
+        && !confirm(&format!(
+            "Update the address components of {} stops?",
+            updates.len()
+        ))?
+    {
+        println!("Aborted, no changes sent");
+        return Ok(());
+    }
+
+    let service = GraphQLService::new(config)?;
+    let applied = update_in_batches(updates, &service).await?;
+    println!("Updated the address components of {} stops", applied);
+    Ok(())
+}
+
+/// Swaps coordinates that are out of range, or outside `bbox`, but valid the
+/// other way round. Returns which stops were fixed.
+pub fn fix_swapped_coordinates(stops: &mut [Stop], bbox: Option<&BoundingBox>) -> Vec<bool> {
+    stops
+        .iter_mut()
+        .map(|stop| {
+            let Some(point) = stop.coordinates() else {
+                return false;
+            };
+            if !looks_swapped(point, bbox) {
+                return false;
+            }
+            let reason = match is_valid_coordinate(point) {
+                true => "outside bbox",
+                false => "out of range",
+            };
+            stop.swap_coordinates();
+            stop.flags.push(format!("lat/lon swapped ({})", reason));
+            true
+        })
+        .collect()
+}
+
+/// Geocodes the stops whose `skip` entry is false.
+async fn geocode_selected(
+    service: &GeocodingService<'_>,
+    stops: &mut [Stop],
+    skip: &[bool],
+) -> Result<(), Box<dyn Error>> {
+    let indices: Vec<usize> = (0..stops.len()).filter(|&i| !skip[i]).collect();
+    let mut selected: Vec<Stop> = indices.iter().map(|&i| stops[i].clone()).collect();
+    service.geocode_stops(&mut selected).await?;
+    for (i, stop) in indices.into_iter().zip(selected) {
+        stops[i] = stop;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fix_swapped_coordinates() {
+        let bbox: BoundingBox = "-74.3,4.4,-73.9,4.9".parse().unwrap();
+        let stop = |latitude: &str, longitude: &str| Stop {
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        };
+        let mut stops = vec![stop("4.6", "-74.1"), stop("-74.1", "4.6"), stop("", "")];
+
+        let fixed = fix_swapped_coordinates(&mut stops, Some(&bbox));
+
+        assert_eq!(fixed, vec![false, true, false]);
+        assert_eq!(stops[1].coordinates(), Some((4.6, -74.1)));
+        assert_eq!(stops[1].flags, vec!["lat/lon swapped (outside bbox)"]);
+        assert!(stops[0].flags.is_empty());
+    }
+
+    #[test]
+    fn test_retain_in_filter_before_geocoding() {
+        let filter = SpatialFilter {
+            bbox: Some("-74.3,4.4,-73.9,4.9".parse().unwrap()),
+            near: None,
+            within: Vec::new(),
+        };
+        let stop = |id: &str, latitude: &str, longitude: &str| Stop {
+            id: id.to_string(),
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        };
+        let stops = vec![
+            stop("in", "4.6", "-74.1"),
+            stop("out", "6.2", "-75.5"),
+            stop("none", "", ""),
+        ];
+        let mut sheets = vec![SheetItems {
+            sheet: "Stops".to_string(),
+            header_row: 1,
+            columns: Default::default(),
+            next_free_column: 3,
+            rows: vec![
+                (2, Stop::default()),
+                (3, Stop::default()),
+                (4, Stop::default()),
+            ],
+        }];
+
+        let (stops, skip) = retain_in_filter(&filter, &mut sheets, stops, vec![true, false, false]);
+
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0].id, "in");
+        assert_eq!(skip, vec![true]);
+        let rows: Vec<u32> = sheets[0].rows.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, vec![2]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{collections::HashMap, error::Error};
+
+use tracing::info;
+
+use crate::{
+    cli::{ClusterArgs, ClusterMethod},
+    config::Config,
+    core::stop::load_stops,
+    utils::{
+        geo::{PointGrid, haversine_m, is_valid_coordinate},
+        xlsx::write_xlsx,
+    },
+};
+
+/// Iteration cap for k-means; it usually settles well before.
+const MAX_KMEANS_ITERATIONS: usize = 100;
+
+/// Groups (latitude, longitude) points into `k` clusters by haversine
+/// distance. Centers start from the first point and then the point farthest
+/// from any chosen center, so results are reproducible.
+pub fn kmeans(points: &[(f64, f64)], k: usize) -> Vec<usize> {
+    if points.is_empty() || k == 0 {
+        return vec![0; points.len()];
+    }
+    let k = k.min(points.len());
+    let nearest = |point: (f64, f64), centers: &[(f64, f64)]| {
+        centers
+            .iter()
+            .map(|&center| haversine_m(point, center))
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0))
+    };
+
+    let mut centers = vec![points[0]];
+    while centers.len() < k {
+        let farthest = points
+            .iter()
+            .max_by(|&&a, &&b| nearest(a, &centers).1.total_cmp(&nearest(b, &centers).1))
+            .copied()
+            .unwrap_or(points[0]);
+        centers.push(farthest);
+    }
+
+    let mut labels = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (label, &point) in labels.iter_mut().zip(points) {
+            let (cluster, _) = nearest(point, &centers);
+            if *label != cluster {
+                *label = cluster;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        let mut sums = vec![(0.0, 0.0, 0usize); k];
+        for (&label, &(lat, lon)) in labels.iter().zip(points) {
+            sums[label].0 += lat;
+            sums[label].1 += lon;
+            sums[label].2 += 1;
+        }
+        for (center, (lat, lon, count)) in centers.iter_mut().zip(sums) {
+            if count > 0 {
+                *center = (lat / count as f64, lon / count as f64);
+            }
+        }
+    }
+    labels
+}
+
+/// Density-based clustering: points with at least `min_points` neighbours
+/// (themselves included) within `eps_m` seed clusters that grow through
+/// their neighbours. Points in no cluster are noise (`None`).
+pub fn dbscan(points: &[(f64, f64)], eps_m: f64, min_points: usize) -> Vec<Option<usize>> {
+    let grid = PointGrid::new(points, eps_m);
+    let mut labels: Vec<Option<usize>> = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut cluster = 0;
+
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        let neighbours = grid.within(i);
+        if neighbours.len() < min_points {
+            continue;
+        }
+        labels[i] = Some(cluster);
+        let mut queue = neighbours;
+        while let Some(j) = queue.pop() {
+            if labels[j].is_none() {
+                labels[j] = Some(cluster);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+            let more = grid.within(j);
+            if more.len() >= min_points {
+                queue.extend(more);
+            }
+        }
+        cluster += 1;
+    }
+    labels
+}
+
+pub async fn process_cluster(args: ClusterArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut stops = load_stops(&args.source, config).await?;
+    let (indices, points): (Vec<usize>, Vec<(f64, f64)>) = stops
+        .iter()
+        .enumerate()
+        .filter_map(|(i, stop)| {
+            stop.coordinates()
+                .filter(|p| is_valid_coordinate(*p))
+                .map(|p| (i, p))
+        })
+        .unzip();
+    if indices.len() < stops.len() {
+        info!(
+            "Skipping {} stops without valid coordinates",
+            stops.len() - indices.len()
+        );
+    }
+
+    let labels: Vec<Option<usize>> = match args.method {
+        ClusterMethod::Kmeans => kmeans(&points, args.clusters)
+            .into_iter()
+            .map(Some)
+            .collect(),
+        ClusterMethod::Dbscan => dbscan(&points, args.eps_m, args.min_points),
+    };
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for (&i, label) in indices.iter().zip(labels) {
+        stops[i].cluster = match label {
+            Some(label) => {
+                *sizes.entry(label).or_default() += 1;
+                (label + 1).to_string()
+            }
+            None => "noise".to_string(),
+        };
+    }
+
+    let mut sizes: Vec<(usize, usize)> = sizes.into_iter().collect();
+    sizes.sort_unstable();
+    println!(
+        "Grouped {} stops into {} clusters",
+        points.len(),
+        sizes.len()
+    );
+    for (label, size) in sizes.iter() {
+        println!("  {:<6} {:>6}", label + 1, size);
+    }
+    let noise = stops.iter().filter(|stop| stop.cluster == "noise").count();
+    if noise > 0 {
+        println!("  {:<6} {:>6}", "noise", noise);
+    }
+
+    write_xlsx(stops, &args.output_file)?;
+    info!("Wrote clustered stops to '{}'", args.output_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_groups() -> Vec<(f64, f64)> {
+        vec![
+            (4.600, -74.100),
+            (4.601, -74.101),
+            (4.602, -74.100),
+            (4.700, -74.000),
+            (4.701, -74.001),
+            (4.650, -74.300),
+        ]
+    }
+
+    #[test]
+    fn test_kmeans() {
+        let labels = kmeans(&two_groups()[..5], 2);
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[1], labels[2]);
+        assert_eq!(labels[3], labels[4]);
+        assert_ne!(labels[0], labels[3]);
+    }
+
+    #[test]
+    fn test_dbscan_marks_noise() {
+        let labels = dbscan(&two_groups(), 500.0, 2);
+        assert_eq!(
+            labels,
+            vec![Some(0), Some(0), Some(0), Some(1), Some(1), None]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    cli::CreateArgs,
+    config::Config,
+    core::stop_import::BATCH_SIZE,
+    models::stop::Stop,
+    mutation::stop_mutation::create_stops,
+    service::graphql::GraphQLService,
+    utils::{
+        prompt::confirm,
+        xlsx::{read_xlsx, write_xlsx},
+    },
+};
+
+/// `StopCreateInput` for a stop read from a spreadsheet.
+#[derive(Deserialize, Serialize, Debug)]
+struct StopCreateData {
+    #[serde(rename = "stopId")]
+    stop_id: String,
+    position: String,
+    latitude: String,
+    longitude: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    organizations: Option<Value>,
+}
+
+impl StopCreateData {
+    fn new(stop: &Stop, organization_id: Option<&str>) -> Self {
+        StopCreateData {
+            stop_id: stop.stop_id.trim().to_string(),
+            position: stop.position.trim().to_string(),
+            latitude: stop.latitude.trim().to_string(),
+            longitude: stop.longitude.trim().to_string(),
+            organizations: organization_id.map(|id| json!({ "connect": [{ "id": id }] })),
+        }
+    }
+}
+
+pub async fn process_create(args: CreateArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let stops: Vec<Stop> = read_xlsx(&args.file_path, &args.sheet_args.read_options())?;
+
+    let (new_stops, existing): (Vec<Stop>, Vec<Stop>) = stops
+        .into_iter()
+        .partition(|stop| stop.id.trim().is_empty());
+    if !existing.is_empty() {
+        warn!(
+            "Skipping {} rows that already have an ID; use `stop import` to update them",
+            existing.len()
+        );
+    }
+    if new_stops.is_empty() {
+        println!("No new stops to create in '{}'", args.file_path);
+        return Ok(());
+    }
+
+    for stop in new_stops.iter() {
+        println!(
+            "+ {} {:?} ({}, {})",
+            stop.stop_id, stop.position, stop.latitude, stop.longitude
+        );
+    }
+    println!("{} stops to create", new_stops.len());
+
+    if args.dry_run {
+        println!("Dry run, no stops created");
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Create {} stops?", new_stops.len()))? {
+        println!("Aborted, no stops created");
+        return Ok(());
+    }
+
+    let organization_id = args
+        .organization_id
+        .as_deref()
+        .or(config.defaults()?.organization_id.as_deref());
+    let service = GraphQLService::new(config)?;
+    let mut created: Vec<Stop> = Vec::new();
+    for batch in new_stops.chunks(BATCH_SIZE) {
+        let data: Vec<StopCreateData> = batch
+            .iter()
+            .map(|stop| StopCreateData::new(stop, organization_id))
+            .collect();
+        match create_stops(data, &service).await {
+            Ok(stops) => created.extend(stops),
+            Err(e) => {
+                return Err(format!(
+                    "Create failed after {} of {} stops were created: {}",
+                    created.len(),
+                    new_stops.len(),
+                    e
+                )
+                .into());
+            }
+        }
+        info!("Created {} of {} stops", created.len(), new_stops.len());
+    }
+
+    println!("Created {} stops", created.len());
+    if let Some(output_file) = args.output_file {
+        write_xlsx(created, &output_file)?;
+    }
+    Ok(())
+}
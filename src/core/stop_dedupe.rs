@@ -0,0 +1,430 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    cli::{DedupeApplyArgs, DedupeCommand, DedupeFindArgs},
+    config::Config,
+    core::{
+        stop::load_stops,
+        stop_import::{BATCH_SIZE, StopChanges},
+    },
+    models::{duplicate::DuplicateRow, stop::Stop},
+    mutation::stop_mutation::{StopWhereUnique, delete_stops, stop_mutation},
+    query::{MutationArgs, MutationsData},
+    service::graphql::GraphQLService,
+    utils::{
+        address::{address_similarity, normalize_address},
+        geo::{PointGrid, haversine_m, is_null_island, is_valid_coordinate},
+        prompt::confirm,
+        xlsx::{ReadOptions, read_xlsx, write_xlsx},
+    },
+};
+
+pub async fn process_dedupe_command(
+    command: DedupeCommand,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        DedupeCommand::Find(args) => find_duplicates(args, config).await,
+        DedupeCommand::Apply(args) => apply_duplicates(args, config).await,
+    }
+}
+
+/// Most stops sharing an address token that are compared pairwise.
+const MAX_BLOCK_SIZE: usize = 200;
+
+/// Minimal union-find over stop indices.
+struct DisjointSet {
+    parent: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        DisjointSet {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// Groups stops that lie within `max_distance_m` of each other or whose
+/// normalized addresses are at least `min_similarity` alike. Groupings are
+/// transitive, and only groups with two or more stops are returned.
+pub fn find_duplicate_groups(
+    stops: &[Stop],
+    max_distance_m: f64,
+    min_similarity: f64,
+) -> Vec<Vec<usize>> {
+    let mut sets = DisjointSet::new(stops.len());
+
+    // Placeholder 0, 0 positions say nothing about where a stop is
+    let (indices, points): (Vec<usize>, Vec<(f64, f64)>) = stops
+        .iter()
+        .enumerate()
+        .filter_map(|(i, stop)| {
+            stop.coordinates()
+                .filter(|&p| is_valid_coordinate(p) && !is_null_island(p))
+                .map(|p| (i, p))
+        })
+        .unzip();
+    if max_distance_m > 0.0 {
+        let grid = PointGrid::new(&points, max_distance_m);
+        for a in 0..points.len() {
+            for b in grid.within(a).into_iter().filter(|&b| b > a) {
+                sets.union(indices[a], indices[b]);
+            }
+        }
+    }
+
+    // Only compare addresses that share a number (house number, postcode),
+    // or a word when they have none. Tokens shared by too many stops, such
+    // as "calle" or the street of a whole route, are left out.
+    let addresses: Vec<String> = stops
+        .iter()
+        .map(|stop| normalize_address(&stop.position))
+        .collect();
+    let mut blocks: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, address) in addresses.iter().enumerate() {
+        if address.is_empty() {
+            continue;
+        }
+        let tokens: HashSet<&str> = address.split(' ').collect();
+        let numbers: Vec<&str> = tokens
+            .iter()
+            .copied()
+            .filter(|token| token.chars().any(|c| c.is_ascii_digit()))
+            .collect();
+        let keys = match numbers.is_empty() {
+            true => tokens.into_iter().collect(),
+            false => numbers,
+        };
+        for key in keys {
+            blocks.entry(key).or_default().push(i);
+        }
+    }
+    let mut compared: HashSet<(usize, usize)> = HashSet::new();
+    for members in blocks.values().filter(|m| m.len() <= MAX_BLOCK_SIZE) {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in members[n + 1..].iter() {
+                if compared.insert((i.min(j), i.max(j)))
+                    && address_similarity(&addresses[i], &addresses[j]) >= min_similarity
+                {
+                    sets.union(i, j);
+                }
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..stops.len() {
+        groups.entry(sets.find(i)).or_default().push(i);
+    }
+    groups.into_values().filter(|g| g.len() > 1).collect()
+}
+
+/// Picks the stop to keep: valid coordinates first, then a stop ID, then the
+/// most detailed address. Ties go to the first stop in the group.
+pub fn suggest_survivor(stops: &[Stop], group: &[usize]) -> usize {
+    let score = |stop: &Stop| {
+        (
+            stop.coordinates()
+                .is_some_and(|p| is_valid_coordinate(p) && !is_null_island(p)),
+            !stop.stop_id.trim().is_empty(),
+            stop.position.trim().len(),
+        )
+    };
+    let mut best = group[0];
+    for &i in group.iter().skip(1) {
+        if score(&stops[i]) > score(&stops[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+async fn find_duplicates(args: DedupeFindArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let stops = load_stops(&args.source, config).await?;
+    let groups = find_duplicate_groups(&stops, args.max_distance_m, args.min_similarity);
+
+    let mut rows = Vec::new();
+    for (n, group) in groups.iter().enumerate() {
+        let survivor = suggest_survivor(&stops, group);
+        let survivor_point = stops[survivor].coordinates();
+        let survivor_address = normalize_address(&stops[survivor].position);
+
+        let ordered =
+            std::iter::once(survivor).chain(group.iter().copied().filter(|&i| i != survivor));
+        for i in ordered {
+            let stop = &stops[i];
+            let distance_m = match (survivor_point, stop.coordinates()) {
+                (Some(a), Some(b)) => format!("{:.1}", haversine_m(a, b)),
+                _ => String::new(),
+            };
+            let similarity =
+                address_similarity(&survivor_address, &normalize_address(&stop.position));
+            rows.push(DuplicateRow {
+                group: (n + 1).to_string(),
+                keep: if i == survivor { "yes" } else { "" }.to_string(),
+                stop: stop.clone(),
+                distance_m,
+                similarity: format!("{:.2}", similarity),
+            });
+        }
+    }
+
+    println!(
+        "Found {} duplicate groups covering {} of {} stops",
+        groups.len(),
+        rows.len(),
+        stops.len()
+    );
+    if rows.is_empty() {
+        return Ok(());
+    }
+    write_xlsx(rows, &args.output_file)?;
+    println!(
+        "Review '{}', adjust the Keep column, then run `stop dedupe apply --file {}`",
+        args.output_file, args.output_file
+    );
+    Ok(())
+}
+
+/// Fills fields the survivor is missing from the first duplicate that has them.
+fn merge_missing_fields(survivor: &Stop, duplicates: &[&Stop]) -> StopChanges {
+    let pick = |current: &str, field: fn(&Stop) -> &str| {
+        if !current.trim().is_empty() {
+            return None;
+        }
+        duplicates
+            .iter()
+            .map(|stop| field(stop).trim())
+            .find(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+
+    let mut changes = StopChanges {
+        stop_id: pick(&survivor.stop_id, |s| &s.stop_id),
+        position: pick(&survivor.position, |s| &s.position),
+        ..Default::default()
+    };
+    // Coordinates are only taken as a pair
+    if survivor.coordinates().is_none()
+        && let Some(stop) = duplicates.iter().find(|stop| stop.coordinates().is_some())
+    {
+        changes.latitude = Some(stop.latitude.trim().to_string());
+        changes.longitude = Some(stop.longitude.trim().to_string());
+    }
+    changes
+}
+
+async fn apply_duplicates(args: DedupeApplyArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<DuplicateRow> = read_xlsx(&args.file_path, &ReadOptions::default())?;
+
+    let mut groups: BTreeMap<String, Vec<&DuplicateRow>> = BTreeMap::new();
+    for row in rows.iter() {
+        groups
+            .entry(row.group.trim().to_string())
+            .or_default()
+            .push(row);
+    }
+
+    let mut updates: Vec<MutationsData<StopChanges, StopWhereUnique>> = Vec::new();
+    let mut deletes: Vec<String> = Vec::new();
+    let mut merged_groups = 0;
+    for (group, members) in groups.iter() {
+        let (kept, removed): (Vec<&DuplicateRow>, Vec<&DuplicateRow>) =
+            members.iter().partition(|row| row.is_kept());
+        if kept.is_empty() {
+            warn!("Group {} has no stop marked Keep, skipping it", group);
+            continue;
+        }
+        if removed.is_empty() {
+            continue;
+        }
+        if kept
+            .iter()
+            .chain(removed.iter())
+            .any(|row| row.stop.id.trim().is_empty())
+        {
+            warn!("Group {} has stops without an ID, skipping it", group);
+            continue;
+        }
+
+        println!(
+            "Group {}: keep {}, delete {}",
+            group,
+            kept.iter()
+                .map(|r| r.stop.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            removed
+                .iter()
+                .map(|r| r.stop.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        if let [survivor] = kept.as_slice() {
+            let duplicates: Vec<&Stop> = removed.iter().map(|row| &row.stop).collect();
+            let changes = merge_missing_fields(&survivor.stop, &duplicates);
+            if !changes.is_empty() {
+                updates.push(MutationsData {
+                    data: changes,
+                    wheres: StopWhereUnique {
+                        id: survivor.stop.id.trim().to_string(),
+                    },
+                });
+            }
+        }
+        deletes.extend(removed.iter().map(|row| row.stop.id.trim().to_string()));
+        merged_groups += 1;
+    }
+
+    if deletes.is_empty() {
+        println!("Nothing to merge");
+        return Ok(());
+    }
+    println!(
+        "{} survivors to update, {} duplicates to delete",
+        updates.len(),
+        deletes.len()
+    );
+    if args.dry_run {
+        println!("Dry run, no changes sent");
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Delete {} duplicate stops?", deletes.len()))? {
+        println!("Aborted, no changes sent");
+        return Ok(());
+    }
+
+    // Update survivors before deleting anything so a failure loses no data
+    let service = GraphQLService::new(config)?;
+    while !updates.is_empty() {
+        let batch: Vec<_> = updates.drain(..BATCH_SIZE.min(updates.len())).collect();
+        stop_mutation(MutationArgs { data: batch }, &service)
+            .await
+            .map_err(|e| format!("Updating survivors failed, nothing deleted: {}", e))?;
+    }
+    let mut deleted = 0;
+    for batch in deletes.chunks(BATCH_SIZE) {
+        deleted += delete_stops(batch, &service).await.map_err(|e| {
+            format!(
+                "Delete failed after {} of {} duplicates were deleted: {}",
+                deleted,
+                deletes.len(),
+                e
+            )
+        })?;
+        info!("Deleted {} of {} duplicates", deleted, deletes.len());
+    }
+
+    println!("Merged {} groups, deleted {} stops", merged_groups, deleted);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(id: &str, position: &str, latitude: &str, longitude: &str) -> Stop {
+        Stop {
+            id: id.to_string(),
+            position: position.to_string(),
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_groups_by_distance() {
+        let stops = vec![
+            stop("a", "Warehouse", "4.600000", "-74.100000"),
+            // About 11 m north of "a"
+            stop("b", "Loading dock", "4.600100", "-74.100000"),
+            stop("c", "Airport", "4.700000", "-74.140000"),
+        ];
+        assert_eq!(find_duplicate_groups(&stops, 25.0, 0.95), vec![vec![0, 1]]);
+        assert!(find_duplicate_groups(&stops, 5.0, 0.95).is_empty());
+    }
+
+    #[test]
+    fn test_null_island_stops_are_not_grouped() {
+        let stops = vec![
+            stop("a", "Warehouse", "0", "0"),
+            stop("b", "Airport", "0.0", "0.0"),
+        ];
+        assert!(find_duplicate_groups(&stops, 25.0, 0.95).is_empty());
+    }
+
+    #[test]
+    fn test_groups_by_address() {
+        let stops = vec![
+            stop("a", "Calle 10 #5-20, Bogotá", "", ""),
+            stop("b", "calle 10 5-20 bogota", "", ""),
+            stop("c", "Carrera 7 #5-20, Bogotá", "", ""),
+        ];
+        assert_eq!(find_duplicate_groups(&stops, 25.0, 0.9), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn test_blocks_on_single_tokens() {
+        let mut stops = vec![
+            stop("a", "Calle 10 #5-20, Bogotá", "", ""),
+            stop("b", "Calle 10 #5-20, Bogotá 1", "", ""),
+            stop("c", "Hacienda Santa Bárbara", "", ""),
+            stop("d", "hacienda santa barbara", "", ""),
+        ];
+        // "10" and "calle" are too common to block on
+        stops.extend(
+            (0..=MAX_BLOCK_SIZE).map(|n| stop("x", &format!("Calle 10 #{}-{}", n, n), "", "")),
+        );
+        assert_eq!(
+            find_duplicate_groups(&stops, 25.0, 0.9),
+            vec![vec![0, 1], vec![2, 3]]
+        );
+    }
+
+    #[test]
+    fn test_suggest_survivor_prefers_complete_stop() {
+        let stops = vec![
+            stop("a", "Calle 10", "", ""),
+            stop("b", "Calle 10 #5-20", "4.6", "-74.1"),
+        ];
+        assert_eq!(suggest_survivor(&stops, &[0, 1]), 1);
+    }
+
+    #[test]
+    fn test_merge_missing_fields() {
+        let survivor = stop("a", "Calle 10 #5-20", "", "");
+        let duplicate = stop("b", "Calle 10", "4.6", "-74.1");
+        let changes = merge_missing_fields(&survivor, &[&duplicate]);
+        assert_eq!(changes.position, None);
+        assert_eq!(changes.latitude.as_deref(), Some("4.6"));
+        assert_eq!(changes.longitude.as_deref(), Some("-74.1"));
+    }
+}
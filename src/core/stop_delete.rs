@@ -0,0 +1,85 @@
+use std::error::Error;
+
+use tracing::{info, warn};
+
+use crate::{
+    cli::DeleteArgs,
+    config::Config,
+    core::stop_import::{BATCH_SIZE, fetch_stops_by_id},
+    models::stop::Stop,
+    mutation::stop_mutation::delete_stops,
+    service::graphql::GraphQLService,
+    utils::{prompt::confirm, xlsx::read_xlsx},
+};
+
+pub async fn process_delete(args: DeleteArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut ids: Vec<String> = match &args.file_path {
+        Some(file_path) => {
+            let stops: Vec<Stop> = read_xlsx(file_path, &args.sheet_args.read_options())?;
+            stops.into_iter().map(|stop| stop.id).collect()
+        }
+        None => args.ids.clone(),
+    };
+    ids = ids
+        .into_iter()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        println!("No stop IDs to delete");
+        return Ok(());
+    }
+
+    let service = GraphQLService::new(config)?;
+    let current = fetch_stops_by_id(&ids, &service).await?;
+    let missing: Vec<&String> = ids.iter().filter(|id| !current.contains_key(*id)).collect();
+    if !missing.is_empty() {
+        warn!(
+            "{} IDs not found in the backend: {:?}",
+            missing.len(),
+            missing
+        );
+    }
+
+    let targets: Vec<&Stop> = ids.iter().filter_map(|id| current.get(id)).collect();
+    if targets.is_empty() {
+        println!("None of the {} IDs exist in the backend", ids.len());
+        return Ok(());
+    }
+    for stop in targets.iter() {
+        println!("- {} {:?} ({})", stop.stop_id, stop.position, stop.id);
+    }
+    println!("{} stops to delete", targets.len());
+
+    if args.dry_run {
+        println!("Dry run, no stops deleted");
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Permanently delete {} stops?", targets.len()))? {
+        println!("Aborted, no stops deleted");
+        return Ok(());
+    }
+
+    let target_ids: Vec<String> = targets.iter().map(|stop| stop.id.clone()).collect();
+    let mut deleted = 0;
+    for batch in target_ids.chunks(BATCH_SIZE) {
+        match delete_stops(batch, &service).await {
+            Ok(count) => deleted += count,
+            Err(e) => {
+                return Err(format!(
+                    "Delete failed after {} of {} stops were deleted: {}",
+                    deleted,
+                    target_ids.len(),
+                    e
+                )
+                .into());
+            }
+        }
+        info!("Deleted {} of {} stops", deleted, target_ids.len());
+    }
+
+    println!("Deleted {} stops", deleted);
+    Ok(())
+}
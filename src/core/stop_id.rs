@@ -0,0 +1,729 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    cli::{IdCommand, OrderBy, StopIDArgs, VerifyIdArgs},
+    config::Config,
+    core::{
+        stop_import::BATCH_SIZE,
+        stop_zone::{assign_zones, zone_names},
+    },
+    models::{
+        renumber::{RenumberEntry, RenumberStatus},
+        stop::Stop,
+    },
+    mutation::stop_mutation::{StopWhereUnique, stop_mutation},
+    query::{MutationArgs, MutationsData, QueryArgs, stop_query::fetch_all_stops},
+    service::graphql::GraphQLService,
+    utils::{
+        generate_id::{CheckDigit, StopIdTemplate, TemplateField},
+        geo::{BoundingBox, hilbert_index, is_valid_coordinate},
+        geojson::read_areas,
+        xlsx::{ReadOptions, read_xlsx, write_xlsx},
+    },
+};
+
+/// Hilbert curve order for `--order-by geo`: a 65536 x 65536 grid over the
+/// organization's stops, finer than any two stops worth telling apart.
+const HILBERT_ORDER: u32 = 16;
+
+#[derive(Deserialize, Serialize, Debug)]
+struct StopData {
+    #[serde(rename = "stopId")]
+    stop_id: String,
+}
+
+/// Value of a template placeholder for `stop`; `{ORG}` is `organization`.
+pub fn template_value(stop: &Stop, field: TemplateField, organization: &str) -> String {
+    match field {
+        TemplateField::Org => organization.to_string(),
+        TemplateField::Zone => stop.zone.clone(),
+        TemplateField::Cluster => stop.cluster.clone(),
+        TemplateField::City => stop.components.city.clone(),
+    }
+}
+
+/// Fails when a stop has nothing for a placeholder of `template`, since its
+/// ID would silently lose that part.
+pub fn check_template_values(
+    template: &StopIdTemplate,
+    stops: &[Stop],
+    organization: &str,
+) -> Result<(), Box<dyn Error>> {
+    for field in template.fields() {
+        let missing = stops
+            .iter()
+            .filter(|stop| template_value(stop, field, organization).trim().is_empty())
+            .count();
+        if missing > 0 {
+            return Err(format!(
+                "{} of {} stops have no {} for '{}'",
+                missing,
+                stops.len(),
+                field.name(),
+                template
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Copies the city and cluster of each row of the spreadsheet at `path` onto
+/// the stop with the same ID.
+fn read_template_fields(stops: &mut [Stop], path: &str) -> Result<(), Box<dyn Error>> {
+    let rows: HashMap<String, Stop> = read_xlsx::<Stop>(path, &ReadOptions::default())?
+        .into_iter()
+        .filter(|row| !row.id.trim().is_empty())
+        .map(|row| (row.id.trim().to_string(), row))
+        .collect();
+    let mut found = 0;
+    for stop in stops.iter_mut() {
+        if let Some(row) = rows.get(stop.id.trim()) {
+            stop.components.city = row.components.city.clone();
+            stop.cluster = row.cluster.clone();
+            found += 1;
+        }
+    }
+    info!("Found {} of {} stops in '{}'", found, stops.len(), path);
+    Ok(())
+}
+
+/// Value of a fetched stop field, by GraphQL name or spreadsheet header.
+fn column_value<'a>(stop: &'a Stop, field: &str) -> Result<&'a str, Box<dyn Error>> {
+    match field.to_lowercase().as_str() {
+        "id" => Ok(&stop.id),
+        "stopid" => Ok(&stop.stop_id),
+        "address" | "position" => Ok(&stop.position),
+        "latitude" => Ok(&stop.latitude),
+        "longitude" | "longtitude" => Ok(&stop.longitude),
+        _ => Err(format!(
+            "Unknown column '{}', expected id, stopId, address, latitude or longitude",
+            field
+        )
+        .into()),
+    }
+}
+
+/// Compares as numbers when both values parse, as text otherwise.
+fn compare_values(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.trim().to_lowercase().cmp(&b.trim().to_lowercase()),
+    }
+}
+
+/// Sorts stops into numbering order. The sort is stable, so ties keep the
+/// backend order. With `geo`, stops without valid coordinates go last.
+pub fn sort_stops(stops: &mut [Stop], order_by: &OrderBy) -> Result<(), Box<dyn Error>> {
+    match order_by {
+        OrderBy::Backend => {}
+        OrderBy::Geo => {
+            let points: Vec<(f64, f64)> = stops
+                .iter()
+                .filter_map(Stop::coordinates)
+                .filter(|p| is_valid_coordinate(*p))
+                .collect();
+            let Some(bbox) = BoundingBox::around(&points) else {
+                return Ok(());
+            };
+            stops.sort_by_cached_key(|stop| {
+                stop.coordinates()
+                    .filter(|p| is_valid_coordinate(*p))
+                    .map(|p| hilbert_index(p, &bbox, HILBERT_ORDER))
+                    .unwrap_or(u64::MAX)
+            });
+        }
+        OrderBy::Address => stops.sort_by_cached_key(|stop| stop.position.trim().to_lowercase()),
+        OrderBy::Column(field) => {
+            if let Some(stop) = stops.first() {
+                column_value(stop, field)?;
+            }
+            stops.sort_by(|a, b| {
+                compare_values(
+                    column_value(a, field).unwrap_or_default(),
+                    column_value(b, field).unwrap_or_default(),
+                )
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Renumbers the stops of an organization in two phases: every stop is
+/// fetched and the whole plan computed before anything is sent, then the plan
+/// is applied in verified batches. Mutating while paging could shift pages.
+pub async fn format_stop_id(args: StopIDArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let organization = args
+        .organization_id
+        .as_deref()
+        .or(config.defaults()?.organization_id.as_deref())
+        .ok_or("No organization; pass --organization or set one in the profile")?;
+    let pattern = match args.pattern.or(config.defaults()?.stop_id_pattern.clone()) {
+        Some(pattern) => pattern,
+        None => StopIdTemplate::parse("ST000000")?,
+    }
+    .with_check_digit(args.checksum);
+
+    // The backend has none of these fields, so they come from files
+    if args.zones_file.is_none() && pattern.fields().contains(&TemplateField::Zone) {
+        return Err("The pattern uses {ZONE}; pass the zones with --zones".into());
+    }
+    if args.fields_file.is_none()
+        && let Some(field) = pattern
+            .fields()
+            .into_iter()
+            .find(|field| matches!(field, TemplateField::City | TemplateField::Cluster))
+    {
+        return Err(format!(
+            "The pattern uses {{{}}}; pass a spreadsheet of the stops with --fields-from",
+            field.name()
+        )
+        .into());
+    }
+
+    let service = GraphQLService::new(config)?;
+    let mut stops = fetch_all_stops(
+        QueryArgs::default().with_organization(organization),
+        &service,
+    )
+    .await?;
+    info!("Fetched {} stops", stops.len());
+    if let Some(zones_file) = &args.zones_file {
+        let areas = read_areas(zones_file)?;
+        assign_zones(&mut stops, &areas, &zone_names(&areas, &args.zone_property));
+    }
+    if let Some(fields_file) = &args.fields_file {
+        read_template_fields(&mut stops, fields_file)?;
+    }
+
+    // Stops that keep their current StopID; the rest are numbered
+    let keeps = |stop: &Stop| {
+        let stop_id = stop.stop_id.trim();
+        !stop_id.is_empty()
+            && (args.only_missing || (args.continue_from_max && pattern.matches(stop_id)))
+    };
+    let (kept, mut numbered): (Vec<Stop>, Vec<Stop>) = stops.into_iter().partition(keeps);
+    if !kept.is_empty() {
+        println!(
+            "Numbering {} stops, keeping {} existing StopIDs",
+            numbered.len(),
+            kept.len()
+        );
+    }
+    check_template_values(&pattern, &numbered, organization)?;
+    sort_stops(&mut numbered, &args.order_by)?;
+
+    let mut generator = pattern.generator();
+    for stop in kept.iter() {
+        generator.reserve(
+            stop.stop_id.trim(),
+            |field| template_value(stop, field, organization),
+            args.continue_from_max,
+        );
+    }
+    let assigned: Vec<(String, &Stop)> = numbered
+        .iter()
+        .map(|stop| {
+            let stop_id = generator.next_id(|field| template_value(stop, field, organization));
+            (stop_id, stop)
+        })
+        .collect();
+
+    let duplicates = duplicate_ids(
+        kept.iter()
+            .map(|stop| stop.stop_id.trim())
+            .chain(assigned.iter().map(|(stop_id, _)| stop_id.as_str())),
+    );
+    if !duplicates.is_empty() {
+        for (stop_id, count) in duplicates.iter().take(10) {
+            println!("- {} used {} times", stop_id, count);
+        }
+        return Err(format!(
+            "{} StopIDs would not be unique in the organization, no changes sent",
+            duplicates.len()
+        )
+        .into());
+    }
+
+    let total = kept.len() + numbered.len();
+    let mut plan: Vec<RenumberEntry> = assigned
+        .into_iter()
+        .filter(|(stop_id, stop)| *stop_id != stop.stop_id)
+        .map(|(stop_id, stop)| RenumberEntry {
+            id: stop.id.clone(),
+            old_stop_id: stop.stop_id.clone(),
+            new_stop_id: stop_id,
+            status: RenumberStatus::Planned,
+            detail: String::new(),
+        })
+        .collect();
+    if plan.is_empty() {
+        println!("All {} stops already have their StopID", total);
+        return Ok(());
+    }
+    println!("Planned {} StopID changes for {} stops", plan.len(), total);
+
+    if args.dry_run {
+        write_xlsx(plan, &args.report_file)?;
+        println!(
+            "Dry run, no changes sent; plan written to '{}'",
+            args.report_file
+        );
+        return Ok(());
+    }
+
+    let result = apply_plan(&mut plan, &service).await;
+    let applied = plan
+        .iter()
+        .filter(|entry| entry.status == RenumberStatus::Applied)
+        .count();
+    let planned = plan.len();
+    write_xlsx(plan, &args.report_file)?;
+    match result {
+        Ok(()) => {
+            println!("Renumbered {} of {} stops", applied, total);
+            info!("Wrote renumbering report to '{}'", args.report_file);
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "Renumbering stopped after {} of {} changes: {}. See '{}' for what was applied",
+            applied, planned, e, args.report_file
+        )
+        .into()),
+    }
+}
+
+/// Applies `plan` in two passes so no StopID is assigned while another stop
+/// of the plan still holds it: stops holding an ID another stop is moving to
+/// are first parked on a temporary StopID, then every stop gets its new one.
+/// Stops at the first batch that fails or does not match; every entry ends up
+/// applied, mismatch, failed or not sent.
+pub async fn apply_plan(
+    plan: &mut [RenumberEntry],
+    service: &GraphQLService,
+) -> Result<(), Box<dyn Error>> {
+    let targets: HashSet<&str> = plan
+        .iter()
+        .map(|entry| entry.new_stop_id.as_str())
+        .collect();
+    let parked: Vec<(usize, String)> = plan
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| targets.contains(entry.old_stop_id.trim()))
+        .map(|(i, entry)| (i, temporary_id(entry)))
+        .collect();
+    let moves: Vec<(usize, String)> = plan
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| (i, entry.new_stop_id.clone()))
+        .collect();
+
+    let mut outcome = send_moves(plan, &parked, service).await;
+    if outcome.is_ok() {
+        outcome = send_moves(plan, &moves, service).await;
+    }
+    plan.iter_mut()
+        .filter(|entry| entry.status == RenumberStatus::Planned)
+        .for_each(|entry| entry.status = RenumberStatus::NotSent);
+    outcome
+}
+
+/// StopID a stop holds between the two passes of `apply_plan`; unique
+/// because stop IDs are.
+fn temporary_id(entry: &RenumberEntry) -> String {
+    format!("TMP-{}", entry.id)
+}
+
+/// Sets the StopIDs of `moves` (plan index, StopID) `BATCH_SIZE` at a time
+/// and checks each StopID the backend returns. Entries reaching their new
+/// StopID become applied; stops at the first batch that fails or mismatches.
+async fn send_moves(
+    plan: &mut [RenumberEntry],
+    moves: &[(usize, String)],
+    service: &GraphQLService,
+) -> Result<(), Box<dyn Error>> {
+    for batch in moves.chunks(BATCH_SIZE) {
+        let data = batch
+            .iter()
+            .map(|(i, stop_id)| MutationsData {
+                data: StopData {
+                    stop_id: stop_id.clone(),
+                },
+                wheres: StopWhereUnique {
+                    id: plan[*i].id.clone(),
+                },
+            })
+            .collect();
+        let stored = match stop_mutation(MutationArgs { data }, service).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                for (i, _) in batch {
+                    plan[*i].status = RenumberStatus::Failed;
+                    plan[*i].detail = e.to_string();
+                }
+                return Err(e);
+            }
+        };
+
+        let stored: HashMap<&str, &str> = stored
+            .iter()
+            .flatten()
+            .map(|stop| (stop.id.as_str(), stop.stop_id.as_str()))
+            .collect();
+        let mut mismatches = 0;
+        for (i, stop_id) in batch {
+            let entry = &mut plan[*i];
+            match stored.get(entry.id.as_str()) {
+                Some(&found) if found == stop_id && *stop_id == entry.new_stop_id => {
+                    entry.status = RenumberStatus::Applied;
+                    entry.detail.clear();
+                }
+                Some(&found) if found == stop_id => {
+                    entry.detail = format!("left at temporary StopID {}", stop_id);
+                }
+                found => {
+                    entry.status = RenumberStatus::Mismatch;
+                    entry.detail = match found {
+                        Some(found) => format!("backend has {}", found),
+                        None => "stop not returned by the backend".to_string(),
+                    };
+                    mismatches += 1;
+                }
+            }
+        }
+        if mismatches > 0 {
+            return Err(format!("{} stops did not get their planned StopID", mismatches).into());
+        }
+    }
+    Ok(())
+}
+
+/// IDs that appear more than once, with their counts, sorted by ID.
+pub fn duplicate_ids<'a>(ids: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for id in ids {
+        *counts.entry(id).or_default() += 1;
+    }
+    let mut duplicates: Vec<(&str, usize)> =
+        counts.into_iter().filter(|(_, count)| *count > 1).collect();
+    duplicates.sort_unstable();
+    duplicates
+}
+
+pub fn process_id_command(command: IdCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        IdCommand::Verify(args) => verify_ids(args),
+    }
+}
+
+/// Prints the IDs whose check character is wrong and fails if there are any.
+fn verify_ids(args: VerifyIdArgs) -> Result<(), Box<dyn Error>> {
+    let mut ids = args.ids;
+    if let Some(path) = &args.file_path {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        ids.extend(text.lines().map(str::to_string));
+    }
+    ids.retain(|id| !id.trim().is_empty());
+    if ids.is_empty() {
+        return Err("No StopIDs to verify; pass them as arguments or with --file".into());
+    }
+
+    let invalid: Vec<&String> = ids.iter().filter(|id| !args.checksum.verify(id)).collect();
+    for id in invalid.iter() {
+        println!(
+            "- {} (expected {})",
+            id.trim(),
+            expected_id(id, args.checksum)
+        );
+    }
+    if !invalid.is_empty() {
+        return Err(format!(
+            "{} of {} StopIDs failed the check",
+            invalid.len(),
+            ids.len()
+        )
+        .into());
+    }
+    println!("All {} StopIDs passed the check", ids.len());
+    Ok(())
+}
+
+/// What `id` would be if its last character were the check character.
+fn expected_id(id: &str, check_digit: CheckDigit) -> String {
+    let id = id.trim();
+    match id.char_indices().last() {
+        Some((i, _)) => check_digit.append(&id[..i]),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendApiSetting, MapBoxClientSetting};
+    use calamine::{Reader, open_workbook_auto};
+
+    fn stop(id: &str, position: &str, latitude: &str, longitude: &str) -> Stop {
+        Stop {
+            id: id.to_string(),
+            position: position.to_string(),
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(stops: &[Stop]) -> Vec<&str> {
+        stops.iter().map(|stop| stop.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sort_stops_geo_keeps_neighbours_adjacent() {
+        let mut stops = vec![
+            stop("a", "", "4.60", "-74.10"),
+            stop("b", "", "4.70", "-74.00"),
+            stop("c", "", "", ""),
+            stop("d", "", "4.601", "-74.101"),
+            stop("e", "", "4.701", "-74.001"),
+        ];
+
+        sort_stops(&mut stops, &OrderBy::Geo).unwrap();
+
+        let order = ids(&stops);
+        let distance = |x: &str, y: &str| {
+            let i = order.iter().position(|id| *id == x).unwrap();
+            let j = order.iter().position(|id| *id == y).unwrap();
+            i.abs_diff(j)
+        };
+        assert_eq!(distance("a", "d"), 1);
+        assert_eq!(distance("b", "e"), 1);
+        assert_eq!(order.last(), Some(&"c"));
+    }
+
+    #[test]
+    fn test_sort_stops_by_column() {
+        let mut stops = vec![
+            stop("a", "Calle 10", "10.5", "0"),
+            stop("b", "carrera 2", "9.5", "0"),
+            stop("c", "Avenida 1", "-1", "0"),
+        ];
+
+        sort_stops(&mut stops, &OrderBy::Column("Latitude".to_string())).unwrap();
+        assert_eq!(ids(&stops), vec!["c", "b", "a"]);
+
+        sort_stops(&mut stops, &OrderBy::Address).unwrap();
+        assert_eq!(ids(&stops), vec!["c", "a", "b"]);
+
+        assert!(sort_stops(&mut stops, &OrderBy::Column("zone".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let ids = ["ST01", "ST02", "ST01", "ST03", "ST02", "ST01"];
+        assert_eq!(
+            duplicate_ids(ids.into_iter()),
+            vec![("ST01", 3), ("ST02", 2)]
+        );
+    }
+
+    fn entry(id: &str, new_stop_id: &str) -> RenumberEntry {
+        RenumberEntry {
+            id: id.to_string(),
+            old_stop_id: String::new(),
+            new_stop_id: new_stop_id.to_string(),
+            status: RenumberStatus::Planned,
+            detail: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_plan_verifies_stored_stop_ids() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"data": {"updateStops": [
+                    {"id": "a", "stopId": "ST01", "position": "", "latitude": "", "longitude": ""},
+                    {"id": "b", "stopId": "ST09", "position": "", "latitude": "", "longitude": ""},
+                    null
+                ]}}"#,
+            )
+            .create();
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
+                base_url: server.url(),
+                api_token: "test_token".into(),
+            }),
+            Ok(MapBoxClientSetting {
+                base_url: "http://example.com".to_string(),
+                map_api_token: "test_token".into(),
+            }),
+        );
+        let service = GraphQLService::new(&config).unwrap();
+        let mut plan = vec![entry("a", "ST01"), entry("b", "ST02"), entry("c", "ST03")];
+
+        let result = apply_plan(&mut plan, &service).await;
+
+        assert!(result.is_err());
+        let statuses: Vec<RenumberStatus> = plan.iter().map(|entry| entry.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                RenumberStatus::Applied,
+                RenumberStatus::Mismatch,
+                RenumberStatus::Mismatch
+            ]
+        );
+        assert_eq!(plan[1].detail, "backend has ST09");
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_apply_plan_swaps_through_temporary_ids() {
+        let mut server = mockito::Server::new_async().await;
+        let parked = server
+            .mock("POST", "/")
+            .match_body(mockito::Matcher::Regex("TMP-a".to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"data": {"updateStops": [
+                    {"id": "a", "stopId": "TMP-a", "position": "", "latitude": "", "longitude": ""},
+                    {"id": "b", "stopId": "TMP-b", "position": "", "latitude": "", "longitude": ""}
+                ]}}"#,
+            )
+            .create();
+        let moved = server
+            .mock("POST", "/")
+            .match_body(mockito::Matcher::Regex(
+                r#""stopId":"ST02".*"stopId":"ST01""#.to_string(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"data": {"updateStops": [
+                    {"id": "a", "stopId": "ST02", "position": "", "latitude": "", "longitude": ""},
+                    {"id": "b", "stopId": "ST01", "position": "", "latitude": "", "longitude": ""}
+                ]}}"#,
+            )
+            .create();
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
+                base_url: server.url(),
+                api_token: "test_token".into(),
+            }),
+            Ok(MapBoxClientSetting {
+                base_url: "http://example.com".to_string(),
+                map_api_token: "test_token".into(),
+            }),
+        );
+        let service = GraphQLService::new(&config).unwrap();
+        let mut a = entry("a", "ST02");
+        a.old_stop_id = "ST01".to_string();
+        let mut b = entry("b", "ST01");
+        b.old_stop_id = "ST02".to_string();
+        let mut plan = vec![a, b];
+
+        apply_plan(&mut plan, &service).await.unwrap();
+
+        assert!(
+            plan.iter()
+                .all(|entry| entry.status == RenumberStatus::Applied)
+        );
+        assert!(plan.iter().all(|entry| entry.detail.is_empty()));
+        parked.assert();
+        moved.assert();
+    }
+
+    #[tokio::test]
+    async fn test_format_stop_id_takes_city_from_spreadsheet() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"data": {"stops": [
+                    {"id": "a", "stopId": "", "position": "", "latitude": "", "longitude": ""},
+                    {"id": "b", "stopId": "", "position": "", "latitude": "", "longitude": ""},
+                    {"id": "c", "stopId": "", "position": "", "latitude": "", "longitude": ""}
+                ]}}"#,
+            )
+            .create();
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
+                base_url: server.url(),
+                api_token: "test_token".into(),
+            }),
+            Ok(MapBoxClientSetting {
+                base_url: "http://example.com".to_string(),
+                map_api_token: "test_token".into(),
+            }),
+        );
+        let dir = std::env::temp_dir();
+        let fields_file = dir.join("veza_cli_stop_id_fields.xlsx");
+        let report_file = dir.join("veza_cli_stop_id_report.xlsx");
+        let city = |id: &str, city: &str| {
+            let mut stop = stop(id, "", "", "");
+            stop.components.city = city.to_string();
+            stop
+        };
+        write_xlsx(
+            vec![
+                city("a", "Bogotá"),
+                city("b", "Medellín"),
+                city("c", "Bogotá"),
+            ],
+            fields_file.to_str().unwrap(),
+        )
+        .unwrap();
+        let args = |fields_file: Option<String>| StopIDArgs {
+            pattern: Some(StopIdTemplate::parse("{CITY3}{SEQ:04}").unwrap()),
+            organization_id: Some("acme".to_string()),
+            zones_file: None,
+            zone_property: "name".to_string(),
+            fields_file,
+            order_by: OrderBy::Backend,
+            checksum: None,
+            only_missing: false,
+            continue_from_max: false,
+            dry_run: true,
+            report_file: report_file.to_str().unwrap().to_string(),
+        };
+
+        let missing = format_stop_id(args(None), &config).await;
+        let result = format_stop_id(
+            args(Some(fields_file.to_str().unwrap().to_string())),
+            &config,
+        )
+        .await;
+        let report = open_workbook_auto(&report_file).map(|mut workbook| {
+            let sheet = workbook.sheet_names()[0].clone();
+            workbook.worksheet_range(&sheet)
+        });
+        std::fs::remove_file(&fields_file).ok();
+        std::fs::remove_file(&report_file).ok();
+
+        assert!(missing.unwrap_err().to_string().contains("{CITY}"));
+        result.unwrap();
+        let new_ids: Vec<String> = report
+            .unwrap()
+            .unwrap()
+            .rows()
+            .skip(1)
+            .map(|row| row[2].to_string())
+            .collect();
+        assert_eq!(new_ids, vec!["BOG0001", "MED0001", "BOG0002"]);
+        mock.assert();
+    }
+}
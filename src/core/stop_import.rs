@@ -0,0 +1,252 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    cli::ImportArgs,
+    config::Config,
+    models::stop::Stop,
+    mutation::stop_mutation::{StopWhereUnique, stop_mutation},
+    query::{MutationArgs, MutationsData, QueryArgs, stop_query::fetch_all_stops},
+    service::graphql::GraphQLService,
+    utils::{prompt::confirm, xlsx::read_xlsx},
+};
+
+/// Number of stops fetched or updated per request.
+pub const BATCH_SIZE: usize = 250;
+
+/// Fields of a stop update. Fields left as `None` are not sent, so the
+/// backend keeps their current value.
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct StopChanges {
+    #[serde(rename = "stopId", skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<String>,
+}
+
+impl StopChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == StopChanges::default()
+    }
+
+    /// (field, new value) pairs for display.
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("stopId", &self.stop_id),
+            ("position", &self.position),
+            ("latitude", &self.latitude),
+            ("longitude", &self.longitude),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_deref().map(|v| (name, v)))
+        .collect()
+    }
+}
+
+/// Compares an edited stop against the backend's current version and keeps
+/// only the fields that actually differ.
+pub fn diff_stop(current: &Stop, edited: &Stop) -> StopChanges {
+    let text = |old: &str, new: &str| (old.trim() != new.trim()).then(|| new.trim().to_string());
+    // Spreadsheets may round-trip coordinates as numbers, so "4.60" equals "4.6"
+    let coordinate =
+        |old: &str, new: &str| match (old.trim().parse::<f64>(), new.trim().parse::<f64>()) {
+            (Ok(a), Ok(b)) if (a - b).abs() < 1e-9 => None,
+            _ => text(old, new),
+        };
+
+    StopChanges {
+        stop_id: text(&current.stop_id, &edited.stop_id),
+        position: text(&current.position, &edited.position),
+        latitude: coordinate(&current.latitude, &edited.latitude),
+        longitude: coordinate(&current.longitude, &edited.longitude),
+    }
+}
+
+/// Fetches the current backend version of each of `ids`, keyed by id.
+pub async fn fetch_stops_by_id(
+    ids: &[String],
+    service: &GraphQLService,
+) -> Result<HashMap<String, Stop>, Box<dyn Error>> {
+    let mut stops = HashMap::new();
+    for chunk in ids.chunks(BATCH_SIZE) {
+        let mut args = QueryArgs::default();
+        args.wheres.insert("id".to_string(), json!({ "in": chunk }));
+        for stop in fetch_all_stops(args, service).await? {
+            stops.insert(stop.id.clone(), stop);
+        }
+    }
+    Ok(stops)
+}
+
+pub async fn process_import(args: ImportArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let edited: Vec<Stop> = read_xlsx(&args.file_path, &args.sheet_args.read_options())?;
+
+    let (edited, without_id): (Vec<Stop>, Vec<Stop>) = edited
+        .into_iter()
+        .partition(|stop| !stop.id.trim().is_empty());
+    if !without_id.is_empty() {
+        warn!(
+            "Skipping {} rows without an ID; use `stop create` for new stops",
+            without_id.len()
+        );
+    }
+
+    let mut seen = HashSet::new();
+    let duplicates: Vec<&str> = edited
+        .iter()
+        .filter(|stop| !seen.insert(stop.id.trim()))
+        .map(|stop| stop.id.trim())
+        .collect();
+    if !duplicates.is_empty() {
+        return Err(format!("Duplicate IDs in '{}': {:?}", args.file_path, duplicates).into());
+    }
+
+    let service = GraphQLService::new(config)?;
+    let ids: Vec<String> = edited
+        .iter()
+        .map(|stop| stop.id.trim().to_string())
+        .collect();
+    let current = fetch_stops_by_id(&ids, &service).await?;
+    info!(
+        "Fetched {} of {} stops from the backend",
+        current.len(),
+        ids.len()
+    );
+
+    let mut changes: Vec<(&Stop, StopChanges)> = Vec::new();
+    let mut missing = Vec::new();
+    for stop in edited.iter() {
+        match current.get(stop.id.trim()) {
+            Some(current_stop) => {
+                let diff = diff_stop(current_stop, stop);
+                if !diff.is_empty() {
+                    changes.push((current_stop, diff));
+                }
+            }
+            None => missing.push(stop.id.trim()),
+        }
+    }
+    if !missing.is_empty() {
+        warn!(
+            "{} IDs not found in the backend: {:?}",
+            missing.len(),
+            missing
+        );
+    }
+
+    if changes.is_empty() {
+        println!("No changes to apply ({} stops unchanged)", edited.len());
+        return Ok(());
+    }
+
+    for (stop, diff) in changes.iter() {
+        println!("{} ({})", stop.stop_id, stop.id);
+        for (field, new) in diff.fields() {
+            let old = match field {
+                "stopId" => &stop.stop_id,
+                "position" => &stop.position,
+                "latitude" => &stop.latitude,
+                _ => &stop.longitude,
+            };
+            println!("  {}: {:?} -> {:?}", field, old, new);
+        }
+    }
+    println!("{} of {} stops changed", changes.len(), edited.len());
+
+    if args.dry_run {
+        println!("Dry run, no changes sent");
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Update {} stops?", changes.len()))? {
+        println!("Aborted, no changes sent");
+        return Ok(());
+    }
+
+    let updates: Vec<MutationsData<StopChanges, StopWhereUnique>> = changes
+        .into_iter()
+        .map(|(stop, diff)| MutationsData {
+            data: diff,
+            wheres: StopWhereUnique {
+                id: stop.id.clone(),
+            },
+        })
+        .collect();
+    let applied = update_in_batches(updates, &service).await?;
+
+    println!("Updated {} stops", applied);
+    Ok(())
+}
+
+/// Sends stop updates `BATCH_SIZE` at a time. On failure the error says how
+/// many stops were already updated, since earlier batches are not rolled back.
+pub async fn update_in_batches<D>(
+    mut updates: Vec<MutationsData<D, StopWhereUnique>>,
+    service: &GraphQLService,
+) -> Result<usize, Box<dyn Error>>
+where
+    D: Serialize + for<'de> Deserialize<'de>,
+{
+    let total = updates.len();
+    let mut applied = 0;
+    while !updates.is_empty() {
+        let batch: Vec<_> = updates.drain(..BATCH_SIZE.min(updates.len())).collect();
+        let count = batch.len();
+        if let Err(e) = stop_mutation(MutationArgs { data: batch }, service).await {
+            return Err(format!(
+                "Update failed after {} of {} stops were applied: {}",
+                applied, total, e
+            )
+            .into());
+        }
+        applied += count;
+        info!("Updated {} of {} stops", applied, total);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(stop_id: &str, position: &str, latitude: &str, longitude: &str) -> Stop {
+        Stop {
+            id: "1".to_string(),
+            stop_id: stop_id.to_string(),
+            position: position.to_string(),
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let current = stop("ST000001", "Main St 1", "4.60", "-74.1");
+        let edited = stop("ST000001", " Main St 1 ", "4.6", "-74.10");
+        assert!(diff_stop(&current, &edited).is_empty());
+    }
+
+    #[test]
+    fn test_diff_only_changed_fields() {
+        let current = stop("ST000001", "Main St 1", "4.6", "-74.1");
+        let edited = stop("ST000001", "Main St 2", "4.6", "-74.2");
+        let diff = diff_stop(&current, &edited);
+
+        assert_eq!(diff.position.as_deref(), Some("Main St 2"));
+        assert_eq!(diff.longitude.as_deref(), Some("-74.2"));
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            json!({ "position": "Main St 2", "longitude": "-74.2" })
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::error::Error;
+
+use tracing::info;
+
+use crate::{
+    cli::{OrderArgs, OrderMethod},
+    config::Config,
+    core::{
+        stop::load_stops,
+        stop_id::{check_template_values, template_value},
+    },
+    models::stop::Stop,
+    utils::{
+        geo::{haversine_m, is_valid_coordinate},
+        xlsx::write_xlsx,
+    },
+};
+
+/// Cap on full 2-opt passes over the route; each pass is quadratic.
+const MAX_TWO_OPT_PASSES: usize = 50;
+
+/// Length in meters of the open path from `depot` through `points` in `order`.
+pub fn path_length_m(depot: (f64, f64), points: &[(f64, f64)], order: &[usize]) -> f64 {
+    let mut previous = depot;
+    let mut length = 0.0;
+    for &i in order {
+        length += haversine_m(previous, points[i]);
+        previous = points[i];
+    }
+    length
+}
+
+/// Visits the closest unvisited point next, starting from `depot`.
+pub fn nearest_neighbour_order(depot: (f64, f64), points: &[(f64, f64)]) -> Vec<usize> {
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut order = Vec::with_capacity(points.len());
+    let mut current = depot;
+    while !remaining.is_empty() {
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .map(|(position, &i)| (position, haversine_m(current, points[i])))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0));
+        let next = remaining.swap_remove(position);
+        current = points[next];
+        order.push(next);
+    }
+    order
+}
+
+/// Shortens an open path from `depot` by reversing segments while that
+/// removes crossings. The route does not return to the depot.
+pub fn two_opt(depot: (f64, f64), points: &[(f64, f64)], order: &mut [usize]) {
+    let n = order.len();
+    let point = |order: &[usize], position: usize| match position {
+        0 => depot,
+        _ => points[order[position - 1]],
+    };
+    for _ in 0..MAX_TWO_OPT_PASSES {
+        let mut improved = false;
+        // Positions are 1-based along the path, 0 being the depot
+        for i in 1..n {
+            for j in i + 1..=n {
+                let (before, first) = (point(order, i - 1), point(order, i));
+                let last = point(order, j);
+                let mut delta = haversine_m(before, last) - haversine_m(before, first);
+                if j < n {
+                    let after = point(order, j + 1);
+                    delta += haversine_m(first, after) - haversine_m(last, after);
+                }
+                if delta < -1e-6 {
+                    order[i - 1..j].reverse();
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+pub async fn process_order(args: OrderArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let stops = load_stops(&args.source, config).await?;
+    let (mut routable, unroutable): (Vec<Stop>, Vec<Stop>) = stops
+        .into_iter()
+        .partition(|stop| stop.coordinates().is_some_and(is_valid_coordinate));
+    let points: Vec<(f64, f64)> = routable.iter().filter_map(Stop::coordinates).collect();
+
+    let mut order = nearest_neighbour_order(args.depot, &points);
+    let nearest_length = path_length_m(args.depot, &points, &order);
+    if let OrderMethod::TwoOpt = args.method {
+        two_opt(args.depot, &points, &mut order);
+    }
+    let length = path_length_m(args.depot, &points, &order);
+
+    let organization = args
+        .source
+        .organization_id
+        .as_deref()
+        .or(config.defaults()?.organization_id.as_deref())
+        .unwrap_or_default();
+    if let Some(pattern) = &args.renumber {
+        check_template_values(pattern, &routable, organization)?;
+    }
+    let renumber = args
+        .renumber
+        .map(|pattern| pattern.with_check_digit(args.checksum));
+    let mut generator = renumber.as_ref().map(|pattern| pattern.generator());
+
+    let mut ordered: Vec<Stop> = Vec::with_capacity(routable.len() + unroutable.len());
+    for (sequence, &i) in order.iter().enumerate() {
+        let mut stop = std::mem::take(&mut routable[i]);
+        stop.sequence = Some(sequence + 1);
+        if let Some(generator) = generator.as_mut() {
+            stop.stop_id = generator.next_id(|field| template_value(&stop, field, organization));
+        }
+        ordered.push(stop);
+    }
+    for mut stop in unroutable {
+        stop.flags.push("no coordinates, not ordered".to_string());
+        ordered.push(stop);
+    }
+
+    println!(
+        "Ordered {} stops, route length {:.1} km",
+        order.len(),
+        length / 1_000.0
+    );
+    if length < nearest_length {
+        println!(
+            "2-opt saved {:.1} km over nearest neighbour",
+            (nearest_length - length) / 1_000.0
+        );
+    }
+    if ordered.len() > order.len() {
+        println!(
+            "{} stops without coordinates were left at the end",
+            ordered.len() - order.len()
+        );
+    }
+    if renumber.is_some() {
+        println!("StopIDs renumbered in route order; apply them with `stop import`");
+    }
+
+    write_xlsx(ordered, &args.output_file)?;
+    info!("Wrote ordered stops to '{}'", args.output_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_neighbour_order() {
+        let depot = (4.6, -74.1);
+        let points = [(4.62, -74.1), (4.61, -74.1), (4.63, -74.1)];
+        assert_eq!(nearest_neighbour_order(depot, &points), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_two_opt_removes_crossing() {
+        let depot = (0.0, 0.0);
+        // Corners of a square visited in a crossing order
+        let points = [(0.0, 0.01), (0.01, 0.0), (0.01, 0.01)];
+        let mut order = vec![0, 1, 2];
+        let before = path_length_m(depot, &points, &order);
+
+        two_opt(depot, &points, &mut order);
+
+        assert!(path_length_m(depot, &points, &order) < before);
+        assert_eq!(order, vec![0, 2, 1]);
+    }
+}
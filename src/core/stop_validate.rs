@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use rust_xlsxwriter::Workbook;
+use tracing::info;
+
+use crate::{
+    cli::ValidateArgs,
+    config::Config,
+    core::stop::load_stops,
+    models::{
+        stop::Stop,
+        validation::{Rule, RuleSummary, ValidationIssue},
+    },
+    utils::{
+        address::is_placeholder,
+        generate_id::StopIdTemplate,
+        geo::{BoundingBox, is_null_island, is_valid_coordinate, looks_swapped},
+        xlsx::add_sheet,
+    },
+};
+
+/// Organization-specific expectations for `validate_stops`.
+#[derive(Debug, Default)]
+pub struct ValidationOptions {
+    pub bbox: Option<BoundingBox>,
+    pub stop_id_pattern: Option<StopIdTemplate>,
+}
+
+/// Runs every rule over `stops` and returns the issues found, in stop order.
+pub fn validate_stops(stops: &[Stop], options: &ValidationOptions) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut issue = |rule: Rule, detail: String, stop: &Stop| {
+        issues.push(ValidationIssue {
+            rule,
+            detail,
+            stop: stop.clone(),
+        })
+    };
+
+    let mut stop_id_counts: HashMap<&str, usize> = HashMap::new();
+    for stop in stops {
+        let stop_id = stop.stop_id.trim();
+        if !stop_id.is_empty() {
+            *stop_id_counts.entry(stop_id).or_default() += 1;
+        }
+    }
+
+    for stop in stops {
+        let address = stop.position.trim();
+        if address.is_empty() || is_placeholder(address) {
+            issue(Rule::EmptyAddress, format!("{:?}", address), stop);
+        }
+
+        match stop.coordinates() {
+            None => issue(
+                Rule::UnparseableCoordinates,
+                format!("{:?}, {:?}", stop.latitude, stop.longitude),
+                stop,
+            ),
+            Some(point) if is_null_island(point) => {
+                issue(Rule::NullIsland, "0, 0".to_string(), stop)
+            }
+            Some(point) if looks_swapped(point, options.bbox.as_ref()) => issue(
+                Rule::SwappedLatLon,
+                format!("{}, {} reads as {}, {}", point.0, point.1, point.1, point.0),
+                stop,
+            ),
+            Some(point) if !is_valid_coordinate(point) => {
+                issue(Rule::OutOfRange, format!("{}, {}", point.0, point.1), stop)
+            }
+            Some(point) => {
+                if let Some(bbox) = &options.bbox
+                    && !bbox.contains(point)
+                {
+                    issue(Rule::OutsideBbox, format!("{}, {}", point.0, point.1), stop)
+                }
+            }
+        }
+
+        let stop_id = stop.stop_id.trim();
+        if let Some(&count) = stop_id_counts.get(stop_id)
+            && count > 1
+        {
+            issue(
+                Rule::DuplicateStopId,
+                format!("{} used {} times", stop_id, count),
+                stop,
+            );
+        }
+        if let Some(pattern) = &options.stop_id_pattern
+            && !pattern.matches(stop_id)
+        {
+            issue(
+                Rule::StopIdPattern,
+                format!("{:?} does not match {}", stop_id, pattern),
+                stop,
+            );
+        }
+    }
+
+    issues
+}
+
+pub async fn process_validate(args: ValidateArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let stops = load_stops(&args.source, config).await?;
+    let options = ValidationOptions {
+        bbox: args.bbox.or(config.defaults()?.bbox),
+        stop_id_pattern: args.pattern.or(config.defaults()?.stop_id_pattern.clone()),
+    };
+    let issues = validate_stops(&stops, &options);
+
+    let mut by_rule: BTreeMap<Rule, Vec<ValidationIssue>> = BTreeMap::new();
+    for issue in issues {
+        by_rule.entry(issue.rule).or_default().push(issue);
+    }
+    let summary: Vec<RuleSummary> = Rule::ALL
+        .iter()
+        .map(|rule| RuleSummary {
+            rule: *rule,
+            count: by_rule.get(rule).map(Vec::len).unwrap_or(0),
+        })
+        .collect();
+
+    println!("Validated {} stops", stops.len());
+    for row in summary.iter() {
+        println!(
+            "  {:<24} {:>6}  {}",
+            row.rule.name(),
+            row.count,
+            row.rule.description()
+        );
+    }
+
+    let mut workbook = Workbook::new();
+    add_sheet(&mut workbook, Some("Summary"), &summary)?;
+    for (rule, issues) in by_rule.iter() {
+        add_sheet(&mut workbook, Some(rule.name()), issues)?;
+    }
+    workbook.save(&args.output_file)?;
+    info!("Wrote validation report to '{}'", args.output_file);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(stop_id: &str, position: &str, latitude: &str, longitude: &str) -> Stop {
+        Stop {
+            id: stop_id.to_string(),
+            stop_id: stop_id.to_string(),
+            position: position.to_string(),
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn rules(stop: Stop, options: &ValidationOptions) -> Vec<Rule> {
+        validate_stops(&[stop], options)
+            .into_iter()
+            .map(|issue| issue.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_stop_has_no_issues() {
+        let options = ValidationOptions {
+            bbox: Some("-74.3,4.4,-73.9,4.9".parse().unwrap()),
+            stop_id_pattern: Some(StopIdTemplate::parse("ST000000").unwrap()),
+        };
+        assert!(rules(stop("ST000001", "Calle 10", "4.6", "-74.1"), &options).is_empty());
+    }
+
+    #[test]
+    fn test_coordinate_rules() {
+        let options = ValidationOptions {
+            bbox: Some("-74.3,4.4,-73.9,4.9".parse().unwrap()),
+            ..Default::default()
+        };
+        let check = |lat: &str, lon: &str| rules(stop("ST1", "Calle 10", lat, lon), &options);
+
+        assert_eq!(check("", "-74.1"), vec![Rule::UnparseableCoordinates]);
+        assert_eq!(check("0", "0"), vec![Rule::NullIsland]);
+        assert_eq!(check("-74.1", "4.6"), vec![Rule::SwappedLatLon]);
+        assert_eq!(check("95", "200"), vec![Rule::OutOfRange]);
+        assert_eq!(check("6.2", "-75.6"), vec![Rule::OutsideBbox]);
+    }
+
+    #[test]
+    fn test_stop_id_rules() {
+        let stops = vec![
+            stop("ST000001", "Calle 10", "4.6", "-74.1"),
+            stop("ST000001", "Calle 11", "4.6", "-74.1"),
+            stop("X-1", "N/A", "4.6", "-74.1"),
+        ];
+        let options = ValidationOptions {
+            stop_id_pattern: Some(StopIdTemplate::parse("ST000000").unwrap()),
+            ..Default::default()
+        };
+        let found: Vec<(Rule, String)> = validate_stops(&stops, &options)
+            .into_iter()
+            .map(|issue| (issue.rule, issue.stop.position))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                (Rule::DuplicateStopId, "Calle 10".to_string()),
+                (Rule::DuplicateStopId, "Calle 11".to_string()),
+                (Rule::EmptyAddress, "N/A".to_string()),
+                (Rule::StopIdPattern, "N/A".to_string()),
+            ]
+        );
+    }
+}
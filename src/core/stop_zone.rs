@@ -0,0 +1,223 @@
+use std::error::Error;
+
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::{
+    cli::ZoneArgs,
+    config::Config,
+    core::{stop::load_stops, stop_import::update_in_batches},
+    models::stop::Stop,
+    mutation::stop_mutation::StopWhereUnique,
+    query::MutationsData,
+    service::graphql::GraphQLService,
+    utils::{
+        geojson::{Area, read_areas},
+        prompt::confirm,
+        xlsx::write_xlsx,
+    },
+};
+
+/// Zone names in file order, from `property` or "Zone N" when it is missing.
+pub fn zone_names(areas: &[Area], property: &str) -> Vec<String> {
+    areas
+        .iter()
+        .enumerate()
+        .map(|(i, area)| {
+            area.property(property)
+                .unwrap_or_else(|| format!("Zone {}", i + 1))
+        })
+        .collect()
+}
+
+/// Sets each stop's zone to the first area containing it and returns, per
+/// stop, the indices of every containing area. Stops without coordinates or
+/// outside every area get an empty list and are flagged, as are overlaps.
+pub fn assign_zones(stops: &mut [Stop], areas: &[Area], names: &[String]) -> Vec<Vec<usize>> {
+    stops
+        .iter_mut()
+        .map(|stop| {
+            let matches: Vec<usize> = match stop.coordinates() {
+                Some(point) => (0..areas.len())
+                    .filter(|&i| areas[i].contains(point))
+                    .collect(),
+                None => Vec::new(),
+            };
+            stop.zone = matches
+                .first()
+                .map(|&i| names[i].clone())
+                .unwrap_or_default();
+            match matches.len() {
+                0 if stop.coordinates().is_none() => stop.flags.push("no coordinates".to_string()),
+                0 => stop.flags.push("outside every zone".to_string()),
+                1 => {}
+                _ => stop.flags.push(format!(
+                    "in overlapping zones: {}",
+                    matches
+                        .iter()
+                        .map(|&i| names[i].as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            }
+            matches
+        })
+        .collect()
+}
+
+pub async fn process_zone(args: ZoneArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let areas = read_areas(&args.zones_file)?;
+    let names = zone_names(&areas, &args.zone_property);
+    let mut stops = load_stops(&args.source, config).await?;
+    let matches = assign_zones(&mut stops, &areas, &names);
+
+    println!("Assigned {} stops to {} zones", stops.len(), areas.len());
+    for (i, name) in names.iter().enumerate() {
+        let count = matches.iter().filter(|m| m.first() == Some(&i)).count();
+        println!("  {:<24} {:>6}", name, count);
+    }
+    let (no_coordinates, outside): (Vec<&Stop>, Vec<&Stop>) = stops
+        .iter()
+        .zip(matches.iter())
+        .filter(|(_, m)| m.is_empty())
+        .map(|(stop, _)| stop)
+        .partition(|stop| stop.coordinates().is_none());
+    for (stops, heading) in [
+        (outside, "outside every zone"),
+        (no_coordinates, "without coordinates"),
+    ] {
+        if !stops.is_empty() {
+            println!("{} stops {}:", stops.len(), heading);
+            for stop in stops {
+                println!("- {} {:?} ({})", stop.stop_id, stop.position, stop.id);
+            }
+        }
+    }
+    let overlapping: Vec<&Stop> = stops
+        .iter()
+        .zip(matches.iter())
+        .filter(|(_, m)| m.len() > 1)
+        .map(|(stop, _)| stop)
+        .collect();
+    if !overlapping.is_empty() {
+        println!(
+            "{} stops in overlapping zones, assigned to the first:",
+            overlapping.len()
+        );
+        for stop in overlapping {
+            println!(
+                "- {} ({}): {}",
+                stop.stop_id,
+                stop.id,
+                stop.flags.last().map(String::as_str).unwrap_or_default()
+            );
+        }
+    }
+
+    // The report is written first so a failed push does not lose it
+    let pushed = args.push_field.is_some().then(|| stops.clone());
+    write_xlsx(stops, &args.output_file)?;
+    info!("Wrote zoned stops to '{}'", args.output_file);
+
+    if let (Some(field), Some(stops)) = (&args.push_field, pushed) {
+        push_zones(&stops, field, &args, config).await?;
+    }
+    Ok(())
+}
+
+/// Writes each zoned stop's zone name to `field` in the backend.
+async fn push_zones(
+    stops: &[Stop],
+    field: &str,
+    args: &ZoneArgs,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let updates: Vec<MutationsData<Value, StopWhereUnique>> = stops
+        .iter()
+        .filter(|stop| !stop.id.trim().is_empty() && !stop.zone.is_empty())
+        .map(|stop| MutationsData {
+            data: Value::Object(Map::from_iter([(
+                field.to_string(),
+                Value::String(stop.zone.clone()),
+            )])),
+            wheres: StopWhereUnique {
+                id: stop.id.trim().to_string(),
+            },
+        })
+        .collect();
+    if updates.is_empty() {
+        println!("No zoned stops with an ID to update");
+        return Ok(());
+    }
+    println!("{} stops to update with their {}", updates.len(), field);
+
+    if args.dry_run {
+        println!("Dry run, no changes sent");
+        return Ok(());
+    }
+    if !args.yes && !confirm(&format!("Set {} on {} stops?", field, updates.len()))? {
+        println!("Aborted, no changes sent");
+        return Ok(());
+    }
+
+    let service = GraphQLService::new(config)?;
+    let applied = update_in_batches(updates, &service).await?;
+    println!("Updated {} on {} stops", field, applied);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::geojson::parse_areas;
+    use serde_json::json;
+
+    fn square(name: &str, min_lon: f64, min_lat: f64, size: f64) -> Value {
+        let (max_lon, max_lat) = (min_lon + size, min_lat + size);
+        json!({
+            "type": "Feature",
+            "properties": { "name": name },
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[
+                    [min_lon, min_lat], [max_lon, min_lat], [max_lon, max_lat],
+                    [min_lon, max_lat], [min_lon, min_lat]
+                ]]
+            }
+        })
+    }
+
+    #[test]
+    fn test_assign_zones() {
+        let areas = parse_areas(&json!({
+            "type": "FeatureCollection",
+            "features": [
+                square("North", -74.2, 4.6, 0.1),
+                square("Center", -74.2, 4.55, 0.1),
+            ]
+        }))
+        .unwrap();
+        let names = zone_names(&areas, "name");
+        let stop = |latitude: &str, longitude: &str| Stop {
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        };
+        let mut stops = vec![
+            stop("4.68", "-74.15"),
+            stop("4.62", "-74.15"),
+            stop("4.0", "-74.15"),
+            stop("", ""),
+        ];
+
+        let matches = assign_zones(&mut stops, &areas, &names);
+
+        assert_eq!(matches, vec![vec![0], vec![0, 1], vec![], vec![]]);
+        assert_eq!(stops[0].zone, "North");
+        assert_eq!(stops[1].zone, "North");
+        assert_eq!(stops[1].flags, vec!["in overlapping zones: North, Center"]);
+        assert_eq!(stops[2].zone, "");
+        assert_eq!(stops[2].flags, vec!["outside every zone"]);
+        assert_eq!(stops[3].flags, vec!["no coordinates"]);
+    }
+}
@@ -10,17 +10,21 @@ mod utils;
 
 use clap::Parser;
 use cli::Cli;
-use config::Config;
+use utils::redact::{Masking, mask};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
+        .with_writer(Masking(std::io::stdout))
         .init();
 
     let cli = Cli::parse();
-    let config = Config::from_env()?;
-    core::run(cli, &config).await?;
+    if let Err(e) = core::run(cli).await {
+        // Errors can quote URLs or responses; print them masked like the logs
+        eprintln!("Error: {}", mask(&e.to_string()));
+        std::process::exit(1);
+    }
 
     Ok(())
 }
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::utils::xlsx::FromExcelRow;
+
+use super::{stop::Stop, traits::Model};
+
+/// One stop in a group of suspected duplicates, as written to the review sheet.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DuplicateRow {
+    pub group: String,
+    /// "yes" for stops to keep when the group is merged.
+    pub keep: String,
+    pub stop: Stop,
+    /// Distance in meters to the suggested survivor.
+    pub distance_m: String,
+    /// Address similarity to the suggested survivor.
+    pub similarity: String,
+}
+
+impl DuplicateRow {
+    pub fn is_kept(&self) -> bool {
+        matches!(
+            self.keep.trim().to_ascii_lowercase().as_str(),
+            "yes" | "y" | "x" | "true" | "1"
+        )
+    }
+}
+
+impl Model for DuplicateRow {
+    fn id(&self) -> &str {
+        &self.stop.id
+    }
+
+    fn display_name() -> &'static str {
+        "Duplicate"
+    }
+
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Group",
+            "Keep",
+            "ID",
+            "StopID",
+            "Address",
+            "Latitude",
+            "Longtitude",
+        ]
+    }
+    fn to_row(&self) -> Vec<String> {
+        let mut row = vec![self.group.clone(), self.keep.clone()];
+        row.extend(self.stop.to_row());
+        row
+    }
+
+    fn extra_headers() -> Vec<&'static str> {
+        vec!["Distance (m)", "Similarity"]
+    }
+    fn to_extra_row(&self) -> Vec<String> {
+        vec![self.distance_m.clone(), self.similarity.clone()]
+    }
+
+    fn numeric_headers() -> Vec<&'static str> {
+        vec![
+            "Group",
+            "Latitude",
+            "Longtitude",
+            "Distance (m)",
+            "Similarity",
+        ]
+    }
+
+    fn map_url(&self) -> Option<String> {
+        self.stop.map_url()
+    }
+}
+
+impl FromExcelRow for DuplicateRow {
+    fn from_row(
+        row: &[calamine::Data],
+        header_map: &std::collections::HashMap<String, usize>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let group_idx = *header_map.get("Group").ok_or("Missing 'Group' column")?;
+        let keep_idx = *header_map.get("Keep").ok_or("Missing 'Keep' column")?;
+        if row.len() <= group_idx {
+            return Err("Row has insufficient columns".into());
+        }
+
+        Ok(DuplicateRow {
+            group: row[group_idx].to_string(),
+            keep: row.get(keep_idx).map(|c| c.to_string()).unwrap_or_default(),
+            stop: Stop::from_row(row, header_map)?,
+            ..Default::default()
+        })
+    }
+}
@@ -1,2 +1,5 @@
+pub mod duplicate;
+pub mod renumber;
 pub mod stop;
 pub mod traits;
+pub mod validation;
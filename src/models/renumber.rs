@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use super::traits::Model;
+
+/// Where a planned StopID change stands.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenumberStatus {
+    Planned,
+    Applied,
+    /// The backend accepted the batch but reported another StopID.
+    Mismatch,
+    /// The batch holding the change was rejected.
+    Failed,
+    /// Not sent because an earlier batch went wrong.
+    NotSent,
+}
+
+impl RenumberStatus {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            RenumberStatus::Planned => "planned",
+            RenumberStatus::Applied => "applied",
+            RenumberStatus::Mismatch => "mismatch",
+            RenumberStatus::Failed => "failed",
+            RenumberStatus::NotSent => "not sent",
+        }
+    }
+}
+
+/// One StopID change of a `format stop-id` plan, as written to its report.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenumberEntry {
+    pub id: String,
+    pub old_stop_id: String,
+    pub new_stop_id: String,
+    pub status: RenumberStatus,
+    pub detail: String,
+}
+
+impl Model for RenumberEntry {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn display_name() -> &'static str {
+        "StopID change"
+    }
+
+    fn headers() -> Vec<&'static str> {
+        vec!["ID", "Old StopID", "New StopID", "Status", "Detail"]
+    }
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.old_stop_id.clone(),
+            self.new_stop_id.clone(),
+            self.status.as_str().to_string(),
+            self.detail.clone(),
+        ]
+    }
+
+    fn highlight_rule() -> Option<(&'static str, &'static [&'static str])> {
+        // Everything that did not go as planned
+        const NOT_APPLIED: &[&str] = &[
+            RenumberStatus::Mismatch.as_str(),
+            RenumberStatus::Failed.as_str(),
+            RenumberStatus::NotSent.as_str(),
+        ];
+        Some(("Status", NOT_APPLIED))
+    }
+}
@@ -4,7 +4,7 @@ use crate::utils::xlsx::FromExcelRow;
 
 use super::traits::Model;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Stop {
     pub id: String,
     pub position: String,
@@ -12,6 +12,111 @@ pub struct Stop {
     pub longitude: String,
     #[serde(rename = "stopId")]
     pub stop_id: String,
+    /// Address as read from the source, before geocoding replaced it.
+    #[serde(skip)]
+    pub original_position: String,
+    /// Cleaned address sent to the geocoder instead of `position`, if set.
+    #[serde(skip)]
+    pub geocode_query: String,
+    /// Parts of the geocoded address, from the geocoder's context.
+    #[serde(skip)]
+    pub components: AddressComponents,
+    /// Name of the delivery zone the stop falls in.
+    #[serde(skip)]
+    pub zone: String,
+    /// Cluster label from `stop cluster`, or "noise".
+    #[serde(skip)]
+    pub cluster: String,
+    /// 1-based position in the route from `stop order`.
+    #[serde(skip)]
+    pub sequence: Option<usize>,
+    /// Where the stop was before being snapped to a road, if it was.
+    #[serde(skip)]
+    pub snap: Option<Snap>,
+    #[serde(skip)]
+    pub geocode_status: GeocodeStatus,
+    /// Geocoder match confidence, e.g. "exact", "high", "medium" or "low".
+    #[serde(skip)]
+    pub geocode_confidence: String,
+    /// Notes about corrections made while formatting, e.g. swapped coordinates.
+    #[serde(skip)]
+    pub flags: Vec<String>,
+}
+
+/// Structured parts of a geocoded address. Serialized in the shape of the
+/// backend's stop fields, leaving out empty parts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressComponents {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub street: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub house_number: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub neighborhood: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub postcode: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub city: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub region: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub country: String,
+}
+
+impl AddressComponents {
+    pub fn is_empty(&self) -> bool {
+        *self == AddressComponents::default()
+    }
+}
+
+/// Point a stop was moved from when snapped to the nearest road.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snap {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub distance_m: f64,
+}
+
+/// Outcome of geocoding a stop, written to the "Status" column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GeocodeStatus {
+    #[default]
+    NotGeocoded,
+    Geocoded,
+    Failed,
+}
+
+impl GeocodeStatus {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            GeocodeStatus::NotGeocoded => "",
+            GeocodeStatus::Geocoded => "OK",
+            GeocodeStatus::Failed => "FAILED",
+        }
+    }
+}
+
+impl Stop {
+    /// Parsed (latitude, longitude), if both columns hold numbers.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        let lat = self.latitude.trim().parse::<f64>().ok()?;
+        let lon = self.longitude.trim().parse::<f64>().ok()?;
+        Some((lat, lon))
+    }
+
+    /// Text sent to the geocoder: the cleaned query if there is one.
+    pub fn geocoder_query(&self) -> &str {
+        match self.geocode_query.is_empty() {
+            true => &self.position,
+            false => &self.geocode_query,
+        }
+    }
+
+    /// Exchanges the latitude and longitude columns.
+    pub fn swap_coordinates(&mut self) {
+        std::mem::swap(&mut self.latitude, &mut self.longitude);
+    }
 }
 
 impl Model for Stop {
@@ -35,6 +140,84 @@ impl Model for Stop {
             self.longitude.clone(),
         ]
     }
+
+    fn extra_headers() -> Vec<&'static str> {
+        vec![
+            "Original Address",
+            "Query",
+            "Street",
+            "Number",
+            "Neighborhood",
+            "Postcode",
+            "City",
+            "Region",
+            "Country",
+            "Zone",
+            "Cluster",
+            "Sequence",
+            "Unsnapped Latitude",
+            "Unsnapped Longtitude",
+            "Snap Distance (m)",
+            "Status",
+            "Confidence",
+            "Flags",
+        ]
+    }
+    fn to_extra_row(&self) -> Vec<String> {
+        let components = &self.components;
+        vec![
+            self.original_position.clone(),
+            self.geocode_query.clone(),
+            components.street.clone(),
+            components.house_number.clone(),
+            components.neighborhood.clone(),
+            components.postcode.clone(),
+            components.city.clone(),
+            components.region.clone(),
+            components.country.clone(),
+            self.zone.clone(),
+            self.cluster.clone(),
+            self.sequence.map(|n| n.to_string()).unwrap_or_default(),
+            self.snap
+                .as_ref()
+                .map(|snap| snap.latitude.to_string())
+                .unwrap_or_default(),
+            self.snap
+                .as_ref()
+                .map(|snap| snap.longitude.to_string())
+                .unwrap_or_default(),
+            self.snap
+                .as_ref()
+                .map(|snap| format!("{:.1}", snap.distance_m))
+                .unwrap_or_default(),
+            self.geocode_status.as_str().to_string(),
+            self.geocode_confidence.clone(),
+            self.flags.join("; "),
+        ]
+    }
+
+    fn numeric_headers() -> Vec<&'static str> {
+        vec![
+            "Latitude",
+            "Longtitude",
+            "Sequence",
+            "Unsnapped Latitude",
+            "Unsnapped Longtitude",
+            "Snap Distance (m)",
+        ]
+    }
+
+    fn map_url(&self) -> Option<String> {
+        let (lat, lon) = self.coordinates()?;
+        Some(format!(
+            "https://www.openstreetmap.org/?mlat={lat}&mlon={lon}#map=18/{lat}/{lon}"
+        ))
+    }
+
+    fn highlight_rule() -> Option<(&'static str, &'static [&'static str])> {
+        const FAILED: &[&str] = &[GeocodeStatus::Failed.as_str()];
+        Some(("Status", FAILED))
+    }
 }
 
 impl FromExcelRow for Stop {
@@ -54,6 +237,15 @@ impl FromExcelRow for Stop {
             .get("Longtitude")
             .ok_or("Missing 'Longtitude' column")?;
 
+        // Columns written by other commands, read back when present
+        let optional = |header: &str| {
+            header_map
+                .get(header)
+                .and_then(|&idx| row.get(idx))
+                .map(|cell| cell.to_string())
+                .unwrap_or_default()
+        };
+
         if row.len() <= id_idx
             || row.len() <= stop_id_idx
             || row.len() <= position_idx
@@ -69,6 +261,13 @@ impl FromExcelRow for Stop {
             position: row[position_idx].to_string(),
             latitude: row[latitude_idx].to_string(),
             longitude: row[longitude_idx].to_string(),
+            zone: optional("Zone"),
+            cluster: optional("Cluster"),
+            components: AddressComponents {
+                city: optional("City"),
+                ..Default::default()
+            },
+            ..Default::default()
         })
     }
 }
@@ -3,6 +3,27 @@ pub trait Model: Serialize + Deserialize<'static> {
     #[allow(unused)]
     fn id(&self) -> &str;
     fn display_name() -> &'static str;
+    /// Columns required when reading the model back from a spreadsheet.
     fn headers() -> Vec<&'static str>;
     fn to_row(&self) -> Vec<String>;
+
+    /// Output-only columns written after `headers()`, not required on read.
+    fn extra_headers() -> Vec<&'static str> {
+        vec![]
+    }
+    fn to_extra_row(&self) -> Vec<String> {
+        vec![]
+    }
+    /// Columns written as numbers when their value parses as one.
+    fn numeric_headers() -> Vec<&'static str> {
+        vec![]
+    }
+    /// Link written to a trailing "Map" column.
+    fn map_url(&self) -> Option<String> {
+        None
+    }
+    /// Header of a column and values; rows holding any of them are highlighted.
+    fn highlight_rule() -> Option<(&'static str, &'static [&'static str])> {
+        None
+    }
 }
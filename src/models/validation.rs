@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+use super::{stop::Stop, traits::Model};
+
+/// A data-quality check run by `stop validate`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    EmptyAddress,
+    UnparseableCoordinates,
+    OutOfRange,
+    NullIsland,
+    SwappedLatLon,
+    OutsideBbox,
+    DuplicateStopId,
+    StopIdPattern,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 8] = [
+        Rule::EmptyAddress,
+        Rule::UnparseableCoordinates,
+        Rule::OutOfRange,
+        Rule::NullIsland,
+        Rule::SwappedLatLon,
+        Rule::OutsideBbox,
+        Rule::DuplicateStopId,
+        Rule::StopIdPattern,
+    ];
+
+    /// Short name, also used as the worksheet name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::EmptyAddress => "empty-address",
+            Rule::UnparseableCoordinates => "unparseable-coordinates",
+            Rule::OutOfRange => "out-of-range",
+            Rule::NullIsland => "null-island",
+            Rule::SwappedLatLon => "swapped-lat-lon",
+            Rule::OutsideBbox => "outside-bbox",
+            Rule::DuplicateStopId => "duplicate-stop-id",
+            Rule::StopIdPattern => "stop-id-pattern",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::EmptyAddress => "Address is empty or a placeholder",
+            Rule::UnparseableCoordinates => "Latitude or longitude is not a number",
+            Rule::OutOfRange => "Latitude outside ±90 or longitude outside ±180",
+            Rule::NullIsland => "Coordinates are (0, 0)",
+            Rule::SwappedLatLon => "Latitude and longitude appear to be swapped",
+            Rule::OutsideBbox => "Stop is outside the organization's bounding box",
+            Rule::DuplicateStopId => "StopID is used by more than one stop",
+            Rule::StopIdPattern => "StopID does not match the organization's pattern",
+        }
+    }
+}
+
+/// One failed check for one stop.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidationIssue {
+    pub rule: Rule,
+    pub detail: String,
+    pub stop: Stop,
+}
+
+impl Model for ValidationIssue {
+    fn id(&self) -> &str {
+        &self.stop.id
+    }
+
+    fn display_name() -> &'static str {
+        "Validation issue"
+    }
+
+    fn headers() -> Vec<&'static str> {
+        vec![
+            "Rule",
+            "Detail",
+            "ID",
+            "StopID",
+            "Address",
+            "Latitude",
+            "Longtitude",
+        ]
+    }
+    fn to_row(&self) -> Vec<String> {
+        let mut row = vec![self.rule.name().to_string(), self.detail.clone()];
+        row.extend(self.stop.to_row());
+        row
+    }
+
+    fn numeric_headers() -> Vec<&'static str> {
+        vec!["Latitude", "Longtitude"]
+    }
+
+    fn map_url(&self) -> Option<String> {
+        self.stop.map_url()
+    }
+}
+
+/// Issue count for one rule, written to the summary sheet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RuleSummary {
+    pub rule: Rule,
+    pub count: usize,
+}
+
+impl Model for RuleSummary {
+    fn id(&self) -> &str {
+        self.rule.name()
+    }
+
+    fn display_name() -> &'static str {
+        "Rule summary"
+    }
+
+    fn headers() -> Vec<&'static str> {
+        vec!["Rule", "Description", "Stops"]
+    }
+    fn to_row(&self) -> Vec<String> {
+        vec![
+            self.rule.name().to_string(),
+            self.rule.description().to_string(),
+            self.count.to_string(),
+        ]
+    }
+
+    fn numeric_headers() -> Vec<&'static str> {
+        vec!["Stops"]
+    }
+}
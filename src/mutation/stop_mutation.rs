@@ -1,11 +1,23 @@
-use crate::{query::MutationArgs, service::graphql::GraphQLService};
+use crate::{
+    models::stop::Stop,
+    query::{GraphQLError, MutationArgs, errors_message},
+    service::graphql::GraphQLService,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+/// `StopWhereUniqueInput` selecting a stop by its backend id.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StopWhereUnique {
+    pub id: String,
+}
+
+/// Updates stops and returns them as the backend stored them, `None` for
+/// stops that were not found.
 pub async fn stop_mutation<'de, D, W>(
     data: MutationArgs<D, W>,
     service: &GraphQLService,
-) -> Result<(), Box<dyn std::error::Error>>
+) -> Result<Vec<Option<Stop>>, Box<dyn std::error::Error>>
 where
     D: Serialize + Deserialize<'de>,
     W: Serialize + Deserialize<'de>,
@@ -22,6 +34,141 @@ where
         }
     "#;
     let request_body: serde_json::Value = json!({ "query": mutation, "variables": data });
-    let _ = service.execute(request_body).await?;
+    let response = service.execute(request_body).await?;
+    check_errors(&response)?;
+    let stops: Vec<Option<Stop>> = serde_json::from_value(response["data"]["updateStops"].clone())
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    Ok(stops)
+}
+
+pub async fn create_stops<D>(
+    data: Vec<D>,
+    service: &GraphQLService,
+) -> Result<Vec<Stop>, Box<dyn std::error::Error>>
+where
+    D: Serialize,
+{
+    let mutation = r#"
+        mutation CreateStops($data: [StopCreateInput!]!) {
+            createStops(data: $data) {
+                id
+                stopId
+                position
+                latitude
+                longitude
+            }
+        }
+    "#;
+    let request_body: serde_json::Value =
+        json!({ "query": mutation, "variables": { "data": data } });
+    let response = service.execute(request_body).await?;
+    check_errors(&response)?;
+    let stops: Vec<Stop> = serde_json::from_value(response["data"]["createStops"].clone())
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+    Ok(stops)
+}
+
+pub async fn delete_stops(
+    ids: &[String],
+    service: &GraphQLService,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mutation = r#"
+        mutation DeleteStops($where: [StopWhereUniqueInput!]!) {
+            deleteStops(where: $where) {
+                id
+            }
+        }
+    "#;
+    let wheres: Vec<StopWhereUnique> = ids
+        .iter()
+        .map(|id| StopWhereUnique { id: id.clone() })
+        .collect();
+    let request_body: serde_json::Value =
+        json!({ "query": mutation, "variables": { "where": wheres } });
+    let response = service.execute(request_body).await?;
+    check_errors(&response)?;
+    // Stops that no longer exist come back as null entries
+    let deleted = response["data"]["deleteStops"]
+        .as_array()
+        .map(|stops| stops.iter().filter(|s| !s.is_null()).count())
+        .unwrap_or(0);
+    Ok(deleted)
+}
+
+/// Turns a GraphQL `errors` array in a mutation response into an error.
+pub fn check_errors(response: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(errors) = response.get("errors")
+        && !errors.is_null()
+    {
+        let errors: Vec<GraphQLError> = serde_json::from_value(errors.clone())
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+        if !errors.is_empty() {
+            return Err(format!("GraphQL error: {}", errors_message(&errors)).into());
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendApiSetting, Config, MapBoxClientSetting};
+    use mockito::Server;
+
+    fn config(url: String) -> Config {
+        Config::with_settings(
+            Ok(BackendApiSetting {
+                base_url: url,
+                api_token: "test_token".into(),
+            }),
+            Ok(MapBoxClientSetting {
+                base_url: "http://example.com".to_string(),
+                map_api_token: "test_token".into(),
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_create_stops() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"data": {"createStops": [
+                    {"id": "new1", "stopId": "ST000001", "position": "Main St 1",
+                     "latitude": "4.6", "longitude": "-74.1"}
+                ]}}"#,
+            )
+            .create();
+        let config = config(server.url());
+        let service = GraphQLService::new(&config).unwrap();
+
+        let stops = create_stops(vec![json!({ "stopId": "ST000001" })], &service)
+            .await
+            .unwrap();
+
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0].id, "new1");
+        mock.assert();
+    }
+
+    #[tokio::test]
+    async fn test_delete_stops_graphql_error() {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"data": null, "errors": [{"message": "Access denied"}]}"#)
+            .create();
+        let config = config(server.url());
+        let service = GraphQLService::new(&config).unwrap();
+
+        let result = delete_stops(&["a".to_string()], &service).await;
+
+        assert!(result.unwrap_err().to_string().contains("Access denied"));
+        mock.assert();
+    }
+}
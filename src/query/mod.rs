@@ -15,6 +15,15 @@ pub struct GraphQLError {
     message: String,
 }
 
+/// Joins GraphQL error messages into one line for error reporting.
+pub fn errors_message(errors: &[GraphQLError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Cursor {
     pub id: String,
@@ -43,6 +52,23 @@ pub struct MutationsData<D, W> {
     pub wheres: W,
 }
 
+impl QueryArgs {
+    /// Restricts the query to stops of one organization.
+    pub fn with_organization(mut self, organization_id: &str) -> Self {
+        self.wheres.insert(
+            "organizations".to_string(),
+            serde_json::json!({
+                "some": {
+                    "id": {
+                        "equals": organization_id
+                    }
+                }
+            }),
+        );
+        self
+    }
+}
+
 impl Default for QueryArgs {
     fn default() -> Self {
         let mut stop_order = HashMap::new();
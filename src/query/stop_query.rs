@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use super::{GraphQLResponse, QueryArgs};
+use super::{Cursor, GraphQLResponse, QueryArgs, errors_message};
 use crate::{models::stop::Stop, service::graphql::GraphQLService};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -45,3 +45,69 @@ pub async fn fetch_stops(
 
     Ok(stop_response)
 }
+
+/// Follows the cursor until every stop matching `args` has been fetched.
+pub async fn fetch_all_stops(
+    args: QueryArgs,
+    service: &GraphQLService,
+) -> Result<Vec<Stop>, Box<dyn std::error::Error>> {
+    fetch_stops_matching(args, service, |_| true).await
+}
+
+/// Like `fetch_all_stops`, but keeps only the stops `keep` accepts, page by
+/// page, for filters the backend cannot evaluate.
+pub async fn fetch_stops_matching(
+    mut args: QueryArgs,
+    service: &GraphQLService,
+    keep: impl Fn(&Stop) -> bool,
+) -> Result<Vec<Stop>, Box<dyn std::error::Error>> {
+    let mut all_stops: Vec<Stop> = Vec::new();
+
+    loop {
+        let response = fetch_stops(&args, service).await?;
+
+        // Check if data exists
+        if let Some(stop_response) = response.data {
+            let count = stop_response.stops.len();
+            let last_id = stop_response.stops.last().map(|stop| stop.id.clone());
+            all_stops.extend(stop_response.stops.into_iter().filter(|stop| keep(stop)));
+
+            // If we received fewer results than `take`, we are done
+            let Some(last_id) = last_id else {
+                break;
+            };
+            if count < args.take.unwrap_or(250) as usize {
+                break;
+            }
+            args.skip = Some(1);
+            args.cursor = Some(Cursor { id: last_id })
+        } else {
+            if let Some(errors) = response.errors {
+                return Err(format!("Failed to fetch stops: {}", errors_message(&errors)).into());
+            }
+            break;
+        }
+    }
+
+    Ok(all_stops)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StopsCountResponse {
+    #[serde(rename = "stopsCount")]
+    pub stops_count: u64,
+}
+
+/// Counts the stops the token can read; the cheapest authenticated query.
+pub async fn count_stops(service: &GraphQLService) -> Result<u64, Box<dyn std::error::Error>> {
+    let request_body = json!({ "query": "query StopsCount { stopsCount }" });
+
+    let response = service.execute(request_body).await?;
+    let count_response: GraphQLResponse<StopsCountResponse> =
+        serde_json::from_value(response).map_err(|e| format!("Failed to parse response: {}", e))?;
+    match (count_response.data, count_response.errors) {
+        (_, Some(errors)) => Err(errors_message(&errors).into()),
+        (Some(data), None) => Ok(data.stops_count),
+        (None, None) => Err("Empty response".into()),
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::Path,
+};
+
+use tracing::info;
+
+use crate::{
+    models::stop::{AddressComponents, Stop},
+    utils::{
+        address::{address_similarity, is_placeholder, normalize_address},
+        geo::is_valid_coordinate,
+        xlsx::{ReadOptions, read_xlsx},
+    },
+};
+
+/// Most candidates, by shared words, compared with the fuzzy matcher per query.
+const MAX_CANDIDATES: usize = 50;
+
+/// Words found in more entries than this, such as "calle" or the city, are
+/// too common to pick candidates by and are skipped.
+const MAX_WORD_ENTRIES: usize = 1_000;
+
+/// A known address with its coordinates.
+#[derive(Debug, Clone, Default)]
+pub struct GazetteerEntry {
+    pub address: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub components: AddressComponents,
+}
+
+/// Local address dataset used to geocode without calling Mapbox.
+///
+/// Addresses are compared on their normalized words in sorted order, so
+/// "Calle 26 45-10, Bogotá" and "45-10 Calle 26 Bogotá" match exactly.
+#[derive(Debug, Default)]
+pub struct Gazetteer {
+    entries: Vec<GazetteerEntry>,
+    keys: Vec<String>,
+    exact: HashMap<String, usize>,
+    words: HashMap<String, Vec<usize>>,
+    /// Lowest similarity a fuzzy match needs to be accepted.
+    pub min_similarity: f64,
+}
+
+/// Normalized words of an address in sorted order.
+fn match_key(address: &str) -> String {
+    let normalized = normalize_address(address);
+    let mut words: Vec<&str> = normalized.split(' ').collect();
+    words.sort_unstable();
+    words.join(" ")
+}
+
+impl Gazetteer {
+    pub fn new(entries: Vec<GazetteerEntry>, min_similarity: f64) -> Self {
+        let mut gazetteer = Gazetteer {
+            min_similarity,
+            ..Default::default()
+        };
+        for entry in entries {
+            let key = match_key(&entry.address);
+            if key.is_empty() {
+                continue;
+            }
+            let i = gazetteer.entries.len();
+            gazetteer.exact.entry(key.clone()).or_insert(i);
+            for word in key.split(' ').collect::<HashSet<_>>() {
+                gazetteer.words.entry(word.to_string()).or_default().push(i);
+            }
+            gazetteer.keys.push(key);
+            gazetteer.entries.push(entry);
+        }
+        gazetteer
+    }
+
+    /// Loads a gazetteer from an OpenAddresses-style CSV, or from a stops
+    /// spreadsheet as written by `stop export` or `format read-xlsx`.
+    pub fn from_file(path: &str, min_similarity: f64) -> Result<Self, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let entries = match extension.as_str() {
+            "csv" => read_csv_entries(path)?,
+            _ => read_stop_entries(path)?,
+        };
+        let gazetteer = Gazetteer::new(entries, min_similarity);
+        if gazetteer.is_empty() {
+            return Err(format!("No usable addresses in gazetteer '{}'", path).into());
+        }
+        info!(
+            "Loaded {} gazetteer addresses from {}",
+            gazetteer.len(),
+            path
+        );
+        Ok(gazetteer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Best entry for `query` with its similarity, 1.0 for an exact match.
+    pub fn lookup(&self, query: &str) -> Option<(&GazetteerEntry, f64)> {
+        let key = match_key(query);
+        if key.is_empty() {
+            return None;
+        }
+        if let Some(&i) = self.exact.get(&key) {
+            return Some((&self.entries[i], 1.0));
+        }
+
+        // Only the rarer words, typically the house number and street number,
+        // pick candidates, so no query walks most of the dataset
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for word in key.split(' ').collect::<HashSet<_>>() {
+            let Some(indexes) = self.words.get(word) else {
+                continue;
+            };
+            if indexes.len() > MAX_WORD_ENTRIES {
+                continue;
+            }
+            for &i in indexes {
+                *shared.entry(i).or_default() += 1;
+            }
+        }
+        let mut candidates: Vec<(usize, usize)> = shared.into_iter().collect();
+        let by_shared = |a: &(usize, usize), b: &(usize, usize)| b.1.cmp(&a.1).then(a.0.cmp(&b.0));
+        if candidates.len() > MAX_CANDIDATES {
+            candidates.select_nth_unstable_by(MAX_CANDIDATES, by_shared);
+            candidates.truncate(MAX_CANDIDATES);
+        }
+        candidates
+            .into_iter()
+            .map(|(i, _)| (i, address_similarity(&key, &self.keys[i])))
+            .filter(|&(_, score)| score >= self.min_similarity)
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map(|(i, score)| (&self.entries[i], score))
+    }
+
+    /// Resolves the stop's geocoder query the way the Mapbox geocoder does,
+    /// replacing its address and coordinates.
+    pub fn geocode(&self, stop: &mut Stop) -> Result<(), Box<dyn Error>> {
+        let (entry, score) = self
+            .lookup(stop.geocoder_query())
+            .ok_or("No gazetteer match for position")?;
+        stop.position = entry.address.clone();
+        stop.latitude = entry.latitude.to_string();
+        stop.longitude = entry.longitude.to_string();
+        stop.components = entry.components.clone();
+        stop.geocode_confidence = match score {
+            1.0 => "exact",
+            score if score >= 0.95 => "high",
+            _ => "medium",
+        }
+        .to_string();
+        Ok(())
+    }
+}
+
+/// Reads an OpenAddresses CSV (LON, LAT, NUMBER, STREET, CITY, REGION,
+/// POSTCODE) or any CSV with Address, Latitude and Longitude columns.
+fn read_csv_entries(path: &str) -> Result<Vec<GazetteerEntry>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let columns: HashMap<String, usize> = reader
+        .headers()?
+        .iter()
+        .enumerate()
+        .map(|(i, header)| (header.trim().to_lowercase(), i))
+        .collect();
+    let column = |names: &[&str]| names.iter().find_map(|name| columns.get(*name).copied());
+    let lat_col = column(&["lat", "latitude"]).ok_or("Missing 'LAT' column")?;
+    let lon_col = column(&["lon", "longitude", "longtitude"]).ok_or("Missing 'LON' column")?;
+    let address_col = column(&["address", "full_address"]);
+    let street_col = column(&["street"]);
+    if address_col.is_none() && street_col.is_none() {
+        return Err("Missing 'STREET' or 'Address' column".into());
+    }
+    let number_col = column(&["number"]);
+    let city_col = column(&["city"]);
+    let region_col = column(&["region"]);
+    let postcode_col = column(&["postcode"]);
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |col: Option<usize>| {
+            col.and_then(|col| record.get(col))
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let (Ok(latitude), Ok(longitude)) = (
+            field(Some(lat_col)).parse::<f64>(),
+            field(Some(lon_col)).parse::<f64>(),
+        ) else {
+            continue;
+        };
+        let components = AddressComponents {
+            street: field(street_col),
+            house_number: field(number_col),
+            postcode: field(postcode_col),
+            city: field(city_col),
+            region: field(region_col),
+            ..Default::default()
+        };
+        let address = match field(address_col) {
+            address if !address.is_empty() => address,
+            _ => [
+                format!("{} {}", components.street, components.house_number),
+                components.city.clone(),
+                components.postcode.clone(),
+            ]
+            .iter()
+            .map(|part| part.trim())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        };
+        entries.push(GazetteerEntry {
+            address,
+            latitude,
+            longitude,
+            components,
+        });
+    }
+    Ok(entries)
+}
+
+/// Uses previously geocoded stops as the dataset, skipping stops without a
+/// usable address or coordinates.
+fn read_stop_entries(path: &str) -> Result<Vec<GazetteerEntry>, Box<dyn Error>> {
+    let stops: Vec<Stop> = read_xlsx(path, &ReadOptions::default())?;
+    Ok(stops
+        .into_iter()
+        .filter(|stop| !is_placeholder(&stop.position))
+        .filter_map(|stop| {
+            let (latitude, longitude) = stop.coordinates()?;
+            is_valid_coordinate((latitude, longitude)).then(|| GazetteerEntry {
+                address: stop.position,
+                latitude,
+                longitude,
+                ..Default::default()
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(address: &str, latitude: f64, longitude: f64) -> GazetteerEntry {
+        GazetteerEntry {
+            address: address.to_string(),
+            latitude,
+            longitude,
+            ..Default::default()
+        }
+    }
+
+    fn gazetteer() -> Gazetteer {
+        Gazetteer::new(
+            vec![
+                entry("Calle 26 # 45-10, Bogotá", 4.6352, -74.0932),
+                entry("Carrera 7 # 72-41, Bogotá", 4.6573, -74.0557),
+            ],
+            0.85,
+        )
+    }
+
+    #[test]
+    fn test_lookup_exact_ignores_order_and_accents() {
+        let gazetteer = gazetteer();
+        let (entry, score) = gazetteer.lookup("45-10 calle 26 BOGOTA").unwrap();
+        assert_eq!(entry.address, "Calle 26 # 45-10, Bogotá");
+        assert_eq!(score, 1.0);
+    }
+
+    #[test]
+    fn test_lookup_fuzzy() {
+        let gazetteer = gazetteer();
+        let (entry, score) = gazetteer.lookup("Carrera 7 # 72-41 Bogta").unwrap();
+        assert_eq!(entry.address, "Carrera 7 # 72-41, Bogotá");
+        assert!(score < 1.0);
+        assert!(gazetteer.lookup("Avenida Boyacá 80").is_none());
+    }
+
+    #[test]
+    fn test_lookup_skips_common_words() {
+        let mut entries: Vec<GazetteerEntry> = (0..=MAX_WORD_ENTRIES)
+            .map(|n| entry(&format!("Calle {} # 10-{}, Bogotá", n, n), 4.6, -74.1))
+            .collect();
+        entries.push(entry("Carrera 7 # 72-41, Bogotá", 4.6573, -74.0557));
+        let gazetteer = Gazetteer::new(entries, 0.85);
+
+        let (entry, _) = gazetteer.lookup("Carrera 7 # 72-41 Bogta").unwrap();
+        assert_eq!(entry.address, "Carrera 7 # 72-41, Bogotá");
+        assert!(gazetteer.lookup("Calle Bogotá").is_none());
+    }
+
+    #[test]
+    fn test_read_openaddresses_csv() {
+        let path = std::env::temp_dir().join("veza_cli_gazetteer.csv");
+        std::fs::write(
+            &path,
+            "LON,LAT,NUMBER,STREET,UNIT,CITY,DISTRICT,REGION,POSTCODE\n\
+             -74.0932,4.6352,45-10,Calle 26,,Bogotá,,Bogotá D.C.,111321\n\
+             bad,row,1,Calle 1,,Bogotá,,,\n",
+        )
+        .unwrap();
+        let gazetteer = Gazetteer::from_file(path.to_str().unwrap(), 0.85).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(gazetteer.len(), 1);
+        let mut stop = Stop {
+            position: "Calle 26 45-10, Bogotá, 111321".to_string(),
+            ..Default::default()
+        };
+        gazetteer.geocode(&mut stop).unwrap();
+        assert_eq!(stop.coordinates(), Some((4.6352, -74.0932)));
+        assert_eq!(stop.components.postcode, "111321");
+        assert_eq!(stop.geocode_confidence, "exact");
+    }
+}
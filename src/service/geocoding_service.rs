@@ -1,40 +1,87 @@
-use crate::{config::Config, models::stop::Stop};
+use crate::{
+    config::Config,
+    models::stop::{AddressComponents, GeocodeStatus, Stop},
+    service::gazetteer::Gazetteer,
+    utils::redact::redact_url,
+};
 use futures::future::join_all;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, Response, StatusCode};
 use secrecy::ExposeSecret;
-use serde_json::Value;
+use serde_json::{Value, json};
 use std::error::Error;
 use std::time::Duration;
 use tracing::{error, info, warn};
 
+/// Most queries Mapbox accepts in one batch request.
+const MAX_BATCH_QUERIES: usize = 1000;
+
 pub struct GeocodingService<'a> {
     client: Client,
     config: &'a Config,
+    /// When set, addresses are resolved against this local dataset instead of Mapbox.
+    gazetteer: Option<Gazetteer>,
+    /// Send queries to the batch endpoint instead of one request per stop.
+    batch: bool,
 }
 
 impl<'a> GeocodingService<'a> {
     pub fn new(client: Client, config: &'a Config) -> Self {
-        GeocodingService { client, config }
+        GeocodingService {
+            client,
+            config,
+            gazetteer: None,
+            batch: false,
+        }
+    }
+
+    /// Uses the Mapbox batch endpoint in `geocode_stops`.
+    pub fn with_batch(mut self, batch: bool) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Geocodes offline against `gazetteer`; no requests are sent to Mapbox.
+    pub fn with_gazetteer(mut self, gazetteer: Gazetteer) -> Self {
+        self.gazetteer = Some(gazetteer);
+        self
+    }
+
+    /// Sends one small forward query and returns the status Mapbox answers
+    /// with, to tell a rejected token from an unreachable endpoint.
+    pub async fn check_token(&self) -> Result<StatusCode, Box<dyn Error>> {
+        let mapbox = self.config.mapbox()?;
+        let base_url = format!("{}/search/geocode/v6/forward", mapbox.base_url);
+        let url = reqwest::Url::parse_with_params(
+            &base_url,
+            &[
+                ("q", "test"),
+                ("limit", "1"),
+                ("access_token", mapbox.map_api_token.expose_secret()),
+            ],
+        )?;
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.without_url())?;
+        Ok(resp.status())
     }
 
     pub async fn geocode_address(&self, stop: &mut Stop) -> Result<(), Box<dyn Error>> {
-        let base_url = format!(
-            "{}/search/geocode/v6/forward",
-            self.config.map_box_client_setting.base_url
-        );
+        if let Some(gazetteer) = &self.gazetteer {
+            return gazetteer.geocode(stop);
+        }
+        let mapbox = self.config.mapbox()?;
+        let base_url = format!("{}/search/geocode/v6/forward", mapbox.base_url);
         let url = reqwest::Url::parse_with_params(
             &base_url,
             &[
-                ("q", stop.position.as_str()),
-                (
-                    "access_token",
-                    self.config
-                        .map_box_client_setting
-                        .map_api_token
-                        .expose_secret(),
-                ),
+                ("q", stop.geocoder_query()),
+                ("access_token", mapbox.map_api_token.expose_secret()),
             ],
         )?;
+        let shown_url = redact_url(&url);
 
         let max_retries = 3;
         let mut attempt = 0;
@@ -49,26 +96,21 @@ impl<'a> GeocodingService<'a> {
                             if attempt >= max_retries {
                                 error!(
                                     "Max retries ({}) reached for {}. Rate limit exceeded.",
-                                    max_retries, url
+                                    max_retries, shown_url
                                 );
                                 return Err("Rate limit exceeded after retries".into());
                             }
 
-                            let retry_after = resp
-                                .headers()
-                                .get("Retry-After")
-                                .and_then(|v| v.to_str().ok())
-                                .and_then(|s| s.parse::<u64>().ok())
-                                .unwrap_or(delay.as_secs());
+                            let retry_after = retry_after(&resp, delay);
 
                             warn!(
                                 "Rate limit hit for {}. Retrying after {}s (attempt {}/{})",
-                                url,
-                                retry_after,
+                                shown_url,
+                                retry_after.as_secs(),
                                 attempt + 1,
                                 max_retries
                             );
-                            tokio::time::sleep(Duration::from_secs(retry_after)).await;
+                            tokio::time::sleep(retry_after).await;
                             attempt += 1;
                             delay *= 2; // Exponential backoff
                             continue;
@@ -78,30 +120,13 @@ impl<'a> GeocodingService<'a> {
                             let json = match json_result {
                                 Ok(value) => value,
                                 Err(e) => {
-                                    error!("Failed to parse JSON from {}: {:?}", url, e);
+                                    let e = e.without_url();
+                                    error!("Failed to parse JSON from {}: {:?}", shown_url, e);
                                     return Err(e.into());
                                 }
                             };
 
-                            let features = json["features"]
-                                .as_array()
-                                .ok_or("No features in Mapbox response")?;
-                            let feature =
-                                features.first().ok_or("No results found for position")?;
-
-                            stop.position = feature["properties"]["full_address"]
-                                .as_str()
-                                .ok_or("Missing full_address")?
-                                .to_string();
-
-                            let coords = feature["geometry"]["coordinates"]
-                                .as_array()
-                                .ok_or("Missing geometry coordinates")?;
-                            stop.longitude =
-                                coords[0].as_f64().ok_or("Invalid longitude")?.to_string();
-                            stop.latitude =
-                                coords[1].as_f64().ok_or("Invalid latitude")?.to_string();
-
+                            apply_feature_collection(stop, &json)?;
                             info!(
                                 "Geocoded {} to ({}, {})",
                                 stop.id, stop.latitude, stop.longitude
@@ -112,7 +137,7 @@ impl<'a> GeocodingService<'a> {
                             error!(
                                 "Unexpected status code {} for {}. Response: {:?}",
                                 other,
-                                url,
+                                shown_url,
                                 resp.text().await
                             );
                             return Err(format!("Unexpected status code: {}", other).into());
@@ -120,7 +145,9 @@ impl<'a> GeocodingService<'a> {
                     }
                 }
                 Err(e) => {
-                    error!("Failed to send request to {}: {:?}", url, e);
+                    // reqwest errors quote the URL, token included
+                    let e = e.without_url();
+                    error!("Failed to send request to {}: {:?}", shown_url, e);
                     return Err(e.into());
                 }
             }
@@ -128,6 +155,29 @@ impl<'a> GeocodingService<'a> {
     }
 
     pub async fn geocode_stops(&self, stops: &mut [Stop]) -> Result<(), Box<dyn Error>> {
+        if let Some(gazetteer) = &self.gazetteer {
+            for stop in stops.iter_mut() {
+                match gazetteer.geocode(stop) {
+                    Ok(()) => stop.geocode_status = GeocodeStatus::Geocoded,
+                    Err(e) => {
+                        warn!("Failed to geocode stop {}: {}", stop.id, e);
+                        stop.geocode_status = GeocodeStatus::Failed;
+                    }
+                }
+            }
+            info!("Geocoded {} stops against the gazetteer", stops.len());
+            return Ok(());
+        }
+        // Fail once up front rather than once per stop
+        self.config.mapbox()?;
+        match self.batch {
+            true => self.geocode_stops_batch(stops).await,
+            false => self.geocode_stops_single(stops).await,
+        }
+    }
+
+    /// Geocodes stops with one request each, staying under the rate limit.
+    async fn geocode_stops_single(&self, stops: &mut [Stop]) -> Result<(), Box<dyn Error>> {
         const REQUESTS_PER_SECOND: usize = 10; // Mapbox free tier limit
         const BATCH_SIZE: usize = REQUESTS_PER_SECOND; // 10 requests per batch
         const BATCH_DELAY: Duration = Duration::from_secs(1); // 1s between batches
@@ -147,8 +197,14 @@ impl<'a> GeocodingService<'a> {
             let results = join_all(chunk_tasks).await;
             for (i, (updated_stop, result)) in results.into_iter().enumerate() {
                 match result {
-                    Ok(()) => chunk[i] = updated_stop,
-                    Err(e) => error!("Failed to geocode stop {}: {}", chunk[i].id, e),
+                    Ok(()) => {
+                        chunk[i] = updated_stop;
+                        chunk[i].geocode_status = GeocodeStatus::Geocoded;
+                    }
+                    Err(e) => {
+                        error!("Failed to geocode stop {}: {}", chunk[i].id, e);
+                        chunk[i].geocode_status = GeocodeStatus::Failed;
+                    }
                 }
             }
 
@@ -166,6 +222,185 @@ impl<'a> GeocodingService<'a> {
         info!("Geocoded {} stops successfully", stops.len());
         Ok(())
     }
+
+    /// Geocodes stops through `/search/geocode/v6/batch`, up to
+    /// `MAX_BATCH_QUERIES` per request. Results map back to stops by index;
+    /// stops without a result, or in a batch that failed, are retried with
+    /// single requests.
+    async fn geocode_stops_batch(&self, stops: &mut [Stop]) -> Result<(), Box<dyn Error>> {
+        let mut retry: Vec<usize> = Vec::new();
+        let mut offset = 0;
+        for chunk in stops.chunks_mut(MAX_BATCH_QUERIES) {
+            let results = match self.send_batch(chunk).await {
+                Ok(results) => results,
+                Err(e) => {
+                    warn!(
+                        "Batch of {} queries failed, falling back to single requests: {}",
+                        chunk.len(),
+                        e
+                    );
+                    Vec::new()
+                }
+            };
+            for (i, stop) in chunk.iter_mut().enumerate() {
+                let geocoded = match results.get(i) {
+                    Some(result) => apply_feature_collection(stop, result).is_ok(),
+                    None => false,
+                };
+                match geocoded {
+                    true => stop.geocode_status = GeocodeStatus::Geocoded,
+                    false => retry.push(offset + i),
+                }
+            }
+            offset += chunk.len();
+        }
+        info!(
+            "Batch geocoded {} of {} stops",
+            stops.len() - retry.len(),
+            stops.len()
+        );
+
+        if !retry.is_empty() {
+            info!("Retrying {} stops with single requests", retry.len());
+            let mut single: Vec<Stop> = retry.iter().map(|&i| stops[i].clone()).collect();
+            self.geocode_stops_single(&mut single).await?;
+            for (i, stop) in retry.into_iter().zip(single) {
+                stops[i] = stop;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends one batch request and returns its FeatureCollections in query order.
+    async fn send_batch(&self, stops: &[Stop]) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mapbox = self.config.mapbox()?;
+        let base_url = format!("{}/search/geocode/v6/batch", mapbox.base_url);
+        let url = reqwest::Url::parse_with_params(
+            &base_url,
+            &[("access_token", mapbox.map_api_token.expose_secret())],
+        )?;
+        let body: Vec<Value> = stops
+            .iter()
+            .map(|stop| json!({ "q": stop.geocoder_query() }))
+            .collect();
+
+        let max_retries = 3;
+        let mut delay = Duration::from_secs(1);
+        for attempt in 0..=max_retries {
+            let resp = self
+                .client
+                .post(url.clone())
+                .json(&body)
+                .send()
+                .await
+                .map_err(|e| e.without_url())?;
+            match resp.status() {
+                StatusCode::TOO_MANY_REQUESTS if attempt < max_retries => {
+                    let retry_after = retry_after(&resp, delay);
+                    warn!(
+                        "Rate limit hit for batch request. Retrying after {}s (attempt {}/{})",
+                        retry_after.as_secs(),
+                        attempt + 1,
+                        max_retries
+                    );
+                    tokio::time::sleep(retry_after).await;
+                    delay *= 2;
+                }
+                StatusCode::OK => {
+                    let json = resp.json::<Value>().await.map_err(|e| e.without_url())?;
+                    let results = json["batch"]
+                        .as_array()
+                        .ok_or("No batch in Mapbox response")?;
+                    if results.len() != stops.len() {
+                        return Err(format!(
+                            "Batch returned {} results for {} queries",
+                            results.len(),
+                            stops.len()
+                        )
+                        .into());
+                    }
+                    return Ok(results.clone());
+                }
+                other => return Err(format!("Unexpected status code: {}", other).into()),
+            }
+        }
+        Err("Rate limit exceeded after retries".into())
+    }
+}
+
+/// Wait asked for by a 429 response's `Retry-After` header in seconds, or
+/// `default` without one.
+fn retry_after(resp: &Response, default: Duration) -> Duration {
+    resp.headers()
+        .get("Retry-After")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .map_or(default, Duration::from_secs)
+}
+
+/// Copies the first feature of a Mapbox v6 FeatureCollection into `stop`.
+/// Leaves `stop` untouched when the feature is incomplete.
+fn apply_feature_collection(stop: &mut Stop, json: &Value) -> Result<(), Box<dyn Error>> {
+    let features = json["features"]
+        .as_array()
+        .ok_or("No features in Mapbox response")?;
+    let feature = features.first().ok_or("No results found for position")?;
+
+    let position = feature["properties"]["full_address"]
+        .as_str()
+        .ok_or("Missing full_address")?;
+    let coords = feature["geometry"]["coordinates"]
+        .as_array()
+        .ok_or("Missing geometry coordinates")?;
+    let longitude = coords
+        .first()
+        .and_then(Value::as_f64)
+        .ok_or("Invalid longitude")?;
+    let latitude = coords
+        .get(1)
+        .and_then(Value::as_f64)
+        .ok_or("Invalid latitude")?;
+
+    stop.position = position.to_string();
+    stop.longitude = longitude.to_string();
+    stop.latitude = latitude.to_string();
+    stop.geocode_confidence = feature["properties"]["match_code"]["confidence"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    stop.components = parse_address_components(&feature["properties"]["context"]);
+    Ok(())
+}
+
+/// Reads street, postcode, city and so on from a Mapbox v6 feature's
+/// `properties.context`. Missing parts are left empty.
+pub fn parse_address_components(context: &Value) -> AddressComponents {
+    let name = |key: &str| {
+        context[key]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let street = match context["address"]["street_name"].as_str() {
+        Some(street) => street.to_string(),
+        None => name("street"),
+    };
+    let city = match name("place") {
+        city if city.is_empty() => name("locality"),
+        city => city,
+    };
+    AddressComponents {
+        street,
+        house_number: context["address"]["address_number"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        neighborhood: name("neighborhood"),
+        postcode: name("postcode"),
+        city,
+        region: name("region"),
+        country: name("country"),
+    }
 }
 
 #[cfg(test)]
@@ -188,7 +423,13 @@ mod tests {
                                 "coordinates": [-74.103439, 4.605241]
                             },
                             "properties": {
-                                "full_address": "Bogotá, 111611, Colombia"
+                                "full_address": "Bogotá, 111611, Colombia",
+                                "context": {
+                                    "postcode": { "name": "111611" },
+                                    "place": { "name": "Bogotá" },
+                                    "region": { "name": "Bogotá D.C." },
+                                    "country": { "name": "Colombia", "country_code": "CO" }
+                                }
                             }
                         }
                     ]
@@ -196,16 +437,16 @@ mod tests {
             )
             .create();
 
-        let config = Config {
-            backend_api_setting: BackendApiSetting {
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
                 base_url: "http://example.com".to_string(),
                 api_token: "test_token".into(),
-            },
-            map_box_client_setting: MapBoxClientSetting {
+            }),
+            Ok(MapBoxClientSetting {
                 base_url: server.url(),
                 map_api_token: "test_token".into(),
-            },
-        };
+            }),
+        );
 
         (mock, config)
     }
@@ -218,16 +459,16 @@ mod tests {
             .with_body(r#"{"features": []}"#)
             .create();
 
-        let config = Config {
-            backend_api_setting: BackendApiSetting {
+        let config = Config::with_settings(
+            Ok(BackendApiSetting {
                 base_url: "http://example.com".to_string(),
                 api_token: "test_token".into(),
-            },
-            map_box_client_setting: MapBoxClientSetting {
+            }),
+            Ok(MapBoxClientSetting {
                 base_url: server.url(),
                 map_api_token: "test_token".into(),
-            },
-        };
+            }),
+        );
 
         (mock, config)
     }
@@ -245,12 +486,17 @@ mod tests {
             latitude: "".to_string(),
             longitude: "".to_string(),
             stop_id: "TS00011".to_string(),
+            ..Default::default()
         };
 
         let result = service.geocode_address(&mut stop).await;
 
         assert!(result.is_ok());
         assert_eq!(stop.position, "Bogotá, 111611, Colombia");
+        assert_eq!(stop.components.postcode, "111611");
+        assert_eq!(stop.components.city, "Bogotá");
+        assert_eq!(stop.components.country, "Colombia");
+        assert!(stop.components.street.is_empty());
         mock.assert();
     }
 
@@ -267,6 +513,7 @@ mod tests {
             latitude: "".to_string(),
             longitude: "".to_string(),
             stop_id: "TS00011".to_string(),
+            ..Default::default()
         };
 
         let result = service.geocode_address(&mut stop).await;
@@ -274,4 +521,145 @@ mod tests {
         assert!(result.is_err());
         mock.assert();
     }
+
+    #[test]
+    fn test_parse_address_components() {
+        let context: Value = serde_json::from_str(
+            r#"{
+                "address": { "address_number": "45-10", "street_name": "Calle 26" },
+                "street": { "name": "Calle 26" },
+                "neighborhood": { "name": "Teusaquillo" },
+                "locality": { "name": "Teusaquillo" },
+                "region": { "name": "Bogotá D.C." }
+            }"#,
+        )
+        .unwrap();
+
+        let components = parse_address_components(&context);
+
+        assert_eq!(components.street, "Calle 26");
+        assert_eq!(components.house_number, "45-10");
+        assert_eq!(components.city, "Teusaquillo");
+        assert!(components.country.is_empty());
+        assert!(parse_address_components(&Value::Null).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_geocode_stops_with_gazetteer_skips_mapbox() {
+        use crate::service::gazetteer::GazetteerEntry;
+
+        let mut server = Server::new_async().await;
+        let (mock, config) = setup_mock_server(&mut server).await;
+        let gazetteer = Gazetteer::new(
+            vec![GazetteerEntry {
+                address: "Calle 26 # 45-10, Bogotá".to_string(),
+                latitude: 4.6352,
+                longitude: -74.0932,
+                ..Default::default()
+            }],
+            0.85,
+        );
+        let service = GeocodingService::new(Client::new(), &config).with_gazetteer(gazetteer);
+        let stop = |position: &str| Stop {
+            position: position.to_string(),
+            ..Default::default()
+        };
+        let mut stops = vec![stop("calle 26 45 10 bogota"), stop("Unknown")];
+
+        service.geocode_stops(&mut stops).await.unwrap();
+
+        assert_eq!(stops[0].geocode_status, GeocodeStatus::Geocoded);
+        assert_eq!(stops[0].coordinates(), Some((4.6352, -74.0932)));
+        assert_eq!(stops[1].geocode_status, GeocodeStatus::Failed);
+        mock.expect(0).assert();
+    }
+
+    #[tokio::test]
+    async fn test_geocode_stops_batch_falls_back_to_single_requests() {
+        let mut server = Server::new_async().await;
+        let batch_mock = server
+            .mock("POST", "/search/geocode/v6/batch")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{
+                    "batch": [
+                        {
+                            "type": "FeatureCollection",
+                            "features": [
+                                {
+                                    "geometry": { "type": "Point", "coordinates": [-74.0557, 4.6573] },
+                                    "properties": { "full_address": "Carrera 7 # 72-41, Bogotá" }
+                                }
+                            ]
+                        },
+                        { "type": "FeatureCollection", "features": [] }
+                    ]
+                }"#,
+            )
+            .create();
+        let (single_mock, config) = setup_mock_server(&mut server).await;
+        let service = GeocodingService::new(Client::new(), &config).with_batch(true);
+        let stop = |position: &str| Stop {
+            position: position.to_string(),
+            ..Default::default()
+        };
+        let mut stops = vec![stop("Carrera 7 72-41"), stop("111611")];
+
+        service.geocode_stops(&mut stops).await.unwrap();
+
+        assert_eq!(stops[0].position, "Carrera 7 # 72-41, Bogotá");
+        assert_eq!(stops[0].geocode_status, GeocodeStatus::Geocoded);
+        assert_eq!(stops[1].position, "Bogotá, 111611, Colombia");
+        assert_eq!(stops[1].geocode_status, GeocodeStatus::Geocoded);
+        batch_mock.assert();
+        single_mock.assert();
+    }
+
+    #[test]
+    fn test_incomplete_feature_leaves_stop_unchanged() {
+        let mut stop = Stop {
+            position: "Calle 26 # 45-10".to_string(),
+            ..Default::default()
+        };
+        let json = json!({
+            "features": [{
+                "geometry": { "type": "Point", "coordinates": [] },
+                "properties": { "full_address": "Calle 26, Bogotá" }
+            }]
+        });
+
+        assert!(apply_feature_collection(&mut stop, &json).is_err());
+        assert_eq!(stop.position, "Calle 26 # 45-10");
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_honours_retry_after() {
+        let mut server = Server::new_async().await;
+        let limited = server
+            .mock("POST", "/search/geocode/v6/batch")
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .expect(1)
+            .create();
+        let accepted = server
+            .mock("POST", "/search/geocode/v6/batch")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"batch": [{ "type": "FeatureCollection", "features": [] }]}"#)
+            .create();
+        let (_, config) = setup_mock_server(&mut server).await;
+        let service = GeocodingService::new(Client::new(), &config);
+
+        let started = std::time::Instant::now();
+        let results = service.send_batch(&[Stop::default()]).await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        limited.assert();
+        accepted.assert();
+    }
 }
@@ -2,32 +2,31 @@ use std::error::Error;
 
 use reqwest::{
     Client,
-    header::{AUTHORIZATION, CONTENT_TYPE},
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderValue},
 };
-use secrecy::ExposeSecret;
+use secrecy::{ExposeSecret, SecretString, zeroize::Zeroize};
 use serde_json::Value;
 use tracing::{error, info};
 
-use crate::Config;
+use crate::{
+    config::{Config, ConfigError},
+    utils::redact::redact_headers,
+};
 
 pub struct GraphQLService {
     client: Client,
     base_url: String,
-    token: String,
+    token: SecretString,
 }
 
 impl GraphQLService {
-    pub fn new(config: &Config) -> Self {
-        GraphQLService {
+    pub fn new(config: &Config) -> Result<Self, ConfigError> {
+        let backend = config.backend()?;
+        Ok(GraphQLService {
             client: Client::new(),
-            base_url: config.backend_api_setting.base_url.clone(),
-            token: config
-                .backend_api_setting
-                .api_token
-                .expose_secret()
-                .to_string()
-                .clone(),
-        }
+            base_url: backend.base_url.clone(),
+            token: backend.api_token.clone(),
+        })
     }
 
     pub async fn execute(&self, query: Value) -> Result<Value, Box<dyn Error>> {
@@ -36,16 +35,30 @@ impl GraphQLService {
             .client
             .post(&self.base_url)
             .header(CONTENT_TYPE, "application/json")
-            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(AUTHORIZATION, self.authorization()?)
             .json(&query)
             .send()
             .await?;
 
         if !response.status().is_success() {
-            error!("GraphQL request failed: {}", response.status());
+            error!(
+                "GraphQL request failed: {} {:?}",
+                response.status(),
+                redact_headers(response.headers())
+            );
             return Err(format!("HTTP error: {}", response.status()).into());
         }
         let json: Value = response.json().await?;
         Ok(json)
     }
+
+    /// The bearer header, marked sensitive so it is left out of Debug output.
+    fn authorization(&self) -> Result<HeaderValue, Box<dyn Error>> {
+        let mut bearer = format!("Bearer {}", self.token.expose_secret());
+        let value = HeaderValue::from_str(&bearer);
+        bearer.zeroize();
+        let mut value = value.map_err(|_| "API token is not a valid header value")?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
 }
@@ -1,2 +1,4 @@
+pub mod gazetteer;
 pub mod geocoding_service;
 pub mod graphql;
+pub mod road_snapper;
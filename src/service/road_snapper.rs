@@ -0,0 +1,131 @@
+use std::error::Error;
+
+use rstar::{RTree, primitives::Line};
+use tracing::info;
+
+use crate::{
+    models::stop::{Snap, Stop},
+    utils::{
+        geo::haversine_m,
+        osm_pbf::{Polyline, read_roads},
+    },
+};
+
+/// Spatial index of road segments for moving stops onto the street network.
+///
+/// Segments are stored in a plane with longitude scaled by the cosine of the
+/// extract's mean latitude, which keeps nearest-segment search accurate for
+/// city- and region-sized extracts.
+pub struct RoadSnapper {
+    tree: RTree<Line<[f64; 2]>>,
+    lon_scale: f64,
+}
+
+impl RoadSnapper {
+    /// Builds the index from road polylines of (latitude, longitude) points.
+    pub fn new(roads: &[Polyline]) -> Self {
+        let points = roads.iter().flatten();
+        let count = points.clone().count().max(1) as f64;
+        let mean_lat = points.map(|point| point.0).sum::<f64>() / count;
+        let lon_scale = mean_lat.to_radians().cos();
+
+        let segments = roads
+            .iter()
+            .flat_map(|road| road.windows(2))
+            .map(|pair| {
+                Line::new(
+                    [pair[0].1 * lon_scale, pair[0].0],
+                    [pair[1].1 * lon_scale, pair[1].0],
+                )
+            })
+            .collect();
+        RoadSnapper {
+            tree: RTree::bulk_load(segments),
+            lon_scale,
+        }
+    }
+
+    /// Loads the drivable roads of an OSM PBF extract.
+    pub fn from_pbf(path: &str) -> Result<Self, Box<dyn Error>> {
+        let roads = read_roads(path)?;
+        if roads.is_empty() {
+            return Err(format!("No roads found in '{}'", path).into());
+        }
+        let snapper = RoadSnapper::new(&roads);
+        info!(
+            "Indexed {} road segments from {}",
+            snapper.tree.size(),
+            path
+        );
+        Ok(snapper)
+    }
+
+    /// Nearest point on any road to a (latitude, longitude) point.
+    pub fn nearest(&self, point: (f64, f64)) -> Option<(f64, f64)> {
+        let query = [point.1 * self.lon_scale, point.0];
+        let segment = self.tree.nearest_neighbor(&query)?;
+        let [x, y] = segment.nearest_point(&query);
+        Some((y, x / self.lon_scale))
+    }
+
+    /// Moves every stop with coordinates onto the nearest road, recording where
+    /// it was and how far it moved. Snaps farther than `max_distance_m` are
+    /// flagged for review. Returns the number of stops snapped.
+    pub fn snap_stops(&self, stops: &mut [Stop], max_distance_m: f64) -> usize {
+        let mut snapped = 0;
+        for stop in stops.iter_mut() {
+            let Some(point) = stop.coordinates() else {
+                continue;
+            };
+            let Some(road_point) = self.nearest(point) else {
+                continue;
+            };
+            let distance_m = haversine_m(point, road_point);
+            stop.latitude = road_point.0.to_string();
+            stop.longitude = road_point.1.to_string();
+            stop.snap = Some(Snap {
+                latitude: point.0,
+                longitude: point.1,
+                distance_m,
+            });
+            if distance_m > max_distance_m {
+                stop.flags.push(format!(
+                    "snapped {:.0} m to road (over {:.0} m)",
+                    distance_m, max_distance_m
+                ));
+            }
+            snapped += 1;
+        }
+        info!("Snapped {} of {} stops to roads", snapped, stops.len());
+        snapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snap_stops() {
+        // An east-west road along latitude 4.6
+        let snapper = RoadSnapper::new(&[vec![(4.6, -74.11), (4.6, -74.09)]]);
+        let stop = |latitude: &str, longitude: &str| Stop {
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            ..Default::default()
+        };
+        let mut stops = vec![stop("4.6002", "-74.1"), stop("4.61", "-74.1"), stop("", "")];
+
+        let snapped = snapper.snap_stops(&mut stops, 50.0);
+
+        assert_eq!(snapped, 2);
+        let (lat, lon) = stops[0].coordinates().unwrap();
+        assert!((lat - 4.6).abs() < 1e-9 && (lon + 74.1).abs() < 1e-9);
+        let snap = stops[0].snap.as_ref().unwrap();
+        assert_eq!((snap.latitude, snap.longitude), (4.6002, -74.1));
+        assert!((snap.distance_m - 22.2).abs() < 0.5);
+        assert!(stops[0].flags.is_empty());
+        assert_eq!(stops[1].flags.len(), 1);
+        assert!(stops[2].snap.is_none());
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet};
+
+/// Address values that mean "no address".
+pub const PLACEHOLDER_ADDRESSES: [&str; 7] = ["n/a", "na", "-", "null", "none", "tbd", "?"];
+
+/// Default abbreviation expansions, matched case-insensitively per word with
+/// an optional trailing period.
+const DEFAULT_ABBREVIATIONS: [(&str, &str); 14] = [
+    ("av", "Avenida"),
+    ("avda", "Avenida"),
+    ("cl", "Calle"),
+    ("cll", "Calle"),
+    ("cra", "Carrera"),
+    ("kr", "Carrera"),
+    ("kra", "Carrera"),
+    ("dg", "Diagonal"),
+    ("tv", "Transversal"),
+    ("trv", "Transversal"),
+    ("ave", "Avenue"),
+    ("blvd", "Boulevard"),
+    ("rd", "Road"),
+    ("no", "#"),
+];
+
+pub fn is_placeholder(address: &str) -> bool {
+    PLACEHOLDER_ADDRESSES.contains(&address.trim().to_lowercase().as_str())
+}
+
+/// Replaces common accented Latin letters with their base letter.
+fn fold_accent(c: char) -> char {
+    match c {
+        'á' | 'à' | 'â' | 'ä' | 'ã' => 'a',
+        'é' | 'è' | 'ê' | 'ë' => 'e',
+        'í' | 'ì' | 'î' | 'ï' => 'i',
+        'ó' | 'ò' | 'ô' | 'ö' | 'õ' => 'o',
+        'ú' | 'ù' | 'û' | 'ü' => 'u',
+        'ñ' => 'n',
+        'ç' => 'c',
+        other => other,
+    }
+}
+
+/// Lowercases an address, folds accents, turns punctuation into spaces and
+/// collapses runs of whitespace, so "Calle 10 #5-20,  Bogotá" and
+/// "calle 10 5 20 bogota" compare equal.
+pub fn normalize_address(address: &str) -> String {
+    address
+        .to_lowercase()
+        .chars()
+        .map(fold_accent)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Similarity of two normalized addresses between 0.0 and 1.0.
+pub fn address_similarity(a: &str, b: &str) -> f64 {
+    strsim::normalized_levenshtein(a, b)
+}
+
+/// Cleans free-text addresses into a geocoder query.
+#[derive(Debug, Clone)]
+pub struct AddressNormalizer {
+    /// Lowercase abbreviation to its expansion.
+    pub abbreviations: HashMap<String, String>,
+    /// Appended when the address does not already contain it, e.g. "Bogotá, Colombia".
+    pub suffix: Option<String>,
+}
+
+impl Default for AddressNormalizer {
+    fn default() -> Self {
+        AddressNormalizer {
+            abbreviations: DEFAULT_ABBREVIATIONS
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            suffix: None,
+        }
+    }
+}
+
+impl AddressNormalizer {
+    /// Removes notes in brackets, placeholders and repeated parts, expands
+    /// abbreviations, tidies whitespace and punctuation, then adds the suffix.
+    pub fn clean(&self, address: &str) -> String {
+        let mut without_notes = String::with_capacity(address.len());
+        let mut depth = 0usize;
+        for c in address.chars() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth = depth.saturating_sub(1),
+                _ if depth == 0 => without_notes.push(c),
+                _ => {}
+            }
+        }
+
+        let mut parts: Vec<String> = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        for part in without_notes.split([',', ';', '|', '\n', '\r']) {
+            self.push_part(part, &mut parts, &mut seen);
+        }
+        // An address that is only a placeholder gets no suffix either
+        if parts.is_empty() {
+            return String::new();
+        }
+        if let Some(suffix) = &self.suffix {
+            for part in suffix.split(',') {
+                self.push_part(part, &mut parts, &mut seen);
+            }
+        }
+
+        parts.join(", ")
+    }
+
+    fn push_part(&self, part: &str, parts: &mut Vec<String>, seen: &mut HashSet<String>) {
+        let words: Vec<&str> = part
+            .split_whitespace()
+            .map(|word| self.expand(word))
+            .collect();
+        let part = words
+            .join(" ")
+            .trim_matches(|c: char| !c.is_alphanumeric() && c != '#')
+            .to_string();
+        if part.is_empty() || is_placeholder(&part) {
+            return;
+        }
+        let key = normalize_address(&part);
+        if !key.is_empty() && seen.insert(key) {
+            parts.push(part);
+        }
+    }
+
+    fn expand<'a>(&'a self, word: &'a str) -> &'a str {
+        let key = word.trim_end_matches('.').to_lowercase();
+        self.abbreviations
+            .get(&key)
+            .map(String::as_str)
+            .unwrap_or(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_address() {
+        assert_eq!(
+            normalize_address("Calle 10 #5-20,  Bogotá\n"),
+            "calle 10 5 20 bogota"
+        );
+        assert_eq!(normalize_address("  "), "");
+    }
+
+    #[test]
+    fn test_address_similarity() {
+        assert_eq!(address_similarity("main st 1", "main st 1"), 1.0);
+        assert!(address_similarity("main st 1", "main st 10") > 0.8);
+        assert!(address_similarity("main st 1", "oak avenue 77") < 0.5);
+    }
+
+    #[test]
+    fn test_clean_address() {
+        let normalizer = AddressNormalizer {
+            suffix: Some("Bogotá, Colombia".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            normalizer.clean("Cra. 7  #32-16 (porteria norte)\nBogota, BOGOTÁ,"),
+            "Carrera 7 #32-16, Bogota, Colombia"
+        );
+        assert_eq!(
+            normalizer.clean("Cl 10 [call before] ,, N/A"),
+            "Calle 10, Bogotá, Colombia"
+        );
+        assert_eq!(normalizer.clean("N/A"), "");
+        assert_eq!(
+            AddressNormalizer::default().clean("  Av  Boyacá "),
+            "Avenida Boyacá"
+        );
+    }
+}
@@ -1,23 +1,497 @@
-pub fn generate_stop_id(pattern: &str, index: usize) -> String {
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use crate::utils::address::normalize_address;
+
+/// Stop field a template placeholder takes its value from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TemplateField {
+    Org,
+    Zone,
+    Cluster,
+    City,
+}
+
+impl TemplateField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateField::Org => "ORG",
+            TemplateField::Zone => "ZONE",
+            TemplateField::Cluster => "CLUSTER",
+            TemplateField::City => "CITY",
+        }
+    }
+}
+
+/// Check character appended to generated IDs so a mistyped ID is caught.
+/// Letters count as their base-36 value in two digits (A = 10), as in IBANs;
+/// other characters such as separators are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckDigit {
+    Luhn,
+    /// Weights 2 to 7 from the right; a remainder of 10 is written as X.
+    Mod11,
+    Damm,
+}
+
+/// Damm's order 10 quasigroup, which catches every single-digit error and
+/// every adjacent transposition.
+const DAMM_TABLE: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+impl CheckDigit {
+    /// Check character for `id`.
+    pub fn compute(&self, id: &str) -> char {
+        let digits = check_digits(id);
+        let digit = match self {
+            CheckDigit::Luhn => {
+                let sum: u32 = digits
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, &d)| match i % 2 {
+                        0 => [0, 2, 4, 6, 8, 1, 3, 5, 7, 9][d as usize],
+                        _ => u32::from(d),
+                    })
+                    .sum();
+                (10 - sum % 10) % 10
+            }
+            CheckDigit::Mod11 => {
+                let sum: u32 = digits
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, &d)| u32::from(d) * (i as u32 % 6 + 2))
+                    .sum();
+                match (11 - sum % 11) % 11 {
+                    10 => return 'X',
+                    digit => digit,
+                }
+            }
+            CheckDigit::Damm => u32::from(
+                digits
+                    .iter()
+                    .fold(0, |interim, &d| DAMM_TABLE[interim as usize][d as usize]),
+            ),
+        };
+        char::from_digit(digit, 10).unwrap_or('0')
+    }
+
+    /// `id` with its check character appended.
+    pub fn append(&self, id: &str) -> String {
+        format!("{}{}", id, self.compute(id))
+    }
+
+    /// Whether the last character of `id` is the check character of the rest.
+    pub fn verify(&self, id: &str) -> bool {
+        let id = id.trim();
+        match id.chars().last() {
+            Some(check) => {
+                let body = &id[..id.len() - check.len_utf8()];
+                !body.is_empty() && check.eq_ignore_ascii_case(&self.compute(body))
+            }
+            None => false,
+        }
+    }
+}
+
+impl FromStr for CheckDigit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "luhn" => Ok(CheckDigit::Luhn),
+            "mod11" | "mod-11" => Ok(CheckDigit::Mod11),
+            "damm" => Ok(CheckDigit::Damm),
+            _ => Err(format!("Expected luhn, mod11 or damm, got '{}'", s)),
+        }
+    }
+}
+
+/// Digits a check character is computed over.
+fn check_digits(id: &str) -> Vec<u8> {
+    let mut digits = Vec::new();
+    for c in id.chars() {
+        match c.to_digit(36) {
+            Some(value) if value < 10 => digits.push(value as u8),
+            Some(value) => digits.extend([(value / 10) as u8, (value % 10) as u8]),
+            None => {}
+        }
+    }
+    digits
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// Field value, cut to its first `len` characters when set.
+    Field {
+        field: TemplateField,
+        len: Option<usize>,
+    },
+    Year,
+    /// Sequence number `start + n + 1`, zero-padded to `width`.
+    Seq {
+        width: usize,
+        start: usize,
+    },
+}
+
+/// A stop ID pattern. Either a legacy pattern such as `ST000000` (prefix and
+/// zero-padded number) or a template such as `{ZONE}-{SEQ:05}` with
+/// placeholders `{ORG}`, `{ZONE}`, `{CLUSTER}`, `{CITY}` (optionally cut, as
+/// in `{CITY3}`), `{YEAR}` and exactly one `{SEQ}` or `{SEQ:<width>}`.
+/// Sequences restart for every combination of field values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopIdTemplate {
+    pattern: String,
+    parts: Vec<Part>,
+    year: i64,
+    check_digit: Option<CheckDigit>,
+}
+
+impl StopIdTemplate {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let parts = match pattern.contains(['{', '}']) {
+            true => parse_template(pattern)?,
+            false => parse_legacy(pattern),
+        };
+        Ok(StopIdTemplate {
+            pattern: pattern.to_string(),
+            parts,
+            year: current_year(),
+            check_digit: None,
+        })
+    }
+
+    /// Appends a check character to every generated ID.
+    pub fn with_check_digit(mut self, check_digit: Option<CheckDigit>) -> Self {
+        self.check_digit = check_digit;
+        self
+    }
+
+    /// Fields the template reads, in order of appearance.
+    pub fn fields(&self) -> Vec<TemplateField> {
+        let mut fields = Vec::new();
+        for part in &self.parts {
+            if let Part::Field { field, .. } = part
+                && !fields.contains(field)
+            {
+                fields.push(*field);
+            }
+        }
+        fields
+    }
+
+    /// The ID for the `index`-th stop (0-based) of its group.
+    pub fn render(&self, value: impl Fn(TemplateField) -> String, index: usize) -> String {
+        let id: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Field { field, len } => field_text(&value(*field), *len),
+                Part::Year => self.year.to_string(),
+                Part::Seq { width, start } => format!("{:0width$}", start + index + 1),
+            })
+            .collect();
+        match self.check_digit {
+            Some(check_digit) => check_digit.append(&id),
+            None => id,
+        }
+    }
+
+    /// Hands out IDs with a separate sequence per group.
+    pub fn generator(&self) -> StopIdGenerator<'_> {
+        StopIdGenerator {
+            template: self,
+            next: HashMap::new(),
+            taken: HashSet::new(),
+        }
+    }
+
+    /// Whether `stop_id` has the shape of IDs generated from this template.
+    /// Sequence numbers may outgrow their padding, but never be shorter.
+    pub fn matches(&self, stop_id: &str) -> bool {
+        self.sequence_index(stop_id).is_some()
+    }
+
+    /// The 0-based sequence index `stop_id` was generated with, if it matches.
+    pub fn sequence_index(&self, stop_id: &str) -> Option<usize> {
+        match self.check_digit {
+            Some(check_digit) => {
+                if !check_digit.verify(stop_id) {
+                    return None;
+                }
+                let (end, _) = stop_id.char_indices().last()?;
+                match_parts(&self.parts, &stop_id[..end])
+            }
+            None => match_parts(&self.parts, stop_id),
+        }
+    }
+}
+
+impl fmt::Display for StopIdTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// Numbers stops from a template, one sequence per group of field values,
+/// skipping IDs that are already taken.
+pub struct StopIdGenerator<'a> {
+    template: &'a StopIdTemplate,
+    next: HashMap<Vec<String>, usize>,
+    taken: HashSet<String>,
+}
+
+impl StopIdGenerator<'_> {
+    /// The field parts as rendered, so values that share an ID prefix (e.g.
+    /// `{CITY3}` of Bogotá and Bogor) share a sequence too.
+    fn group(&self, value: &impl Fn(TemplateField) -> String) -> Vec<String> {
+        self.template
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Field { field, len } => Some(field_text(&value(*field), *len)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Keeps `stop_id` from being handed out. When it is this template's ID
+    /// for the group of `value`, that group continues after it.
+    pub fn reserve(
+        &mut self,
+        stop_id: &str,
+        value: impl Fn(TemplateField) -> String,
+        continue_after: bool,
+    ) {
+        if continue_after
+            && let Some(index) = self.template.sequence_index(stop_id)
+            && self.template.render(&value, index) == stop_id
+        {
+            let next = self.next.entry(self.group(&value)).or_default();
+            *next = (*next).max(index + 1);
+        }
+        self.taken.insert(stop_id.to_string());
+    }
+
+    pub fn next_id(&mut self, value: impl Fn(TemplateField) -> String) -> String {
+        let group = self.group(&value);
+        let index = self.next.entry(group).or_default();
+        let mut id = self.template.render(&value, *index);
+        while self.taken.contains(&id) {
+            *index += 1;
+            id = self.template.render(&value, *index);
+        }
+        *index += 1;
+        self.taken.insert(id.clone());
+        id
+    }
+}
+
+/// "Non-digit prefix + number": the number is where the sequence starts and
+/// its length the padding. Without a number, IDs are the prefix and 1, 2...
+fn parse_legacy(pattern: &str) -> Vec<Part> {
     let prefix_end = pattern.chars().take_while(|c| !c.is_ascii_digit()).count();
     let (prefix, num_part) = pattern.split_at(prefix_end);
+    let seq = match num_part.parse::<usize>() {
+        Ok(start) => Part::Seq {
+            width: num_part.len(),
+            start,
+        },
+        Err(_) => Part::Seq { width: 0, start: 0 },
+    };
+    vec![Part::Literal(prefix.to_string()), seq]
+}
 
-    match num_part.parse::<usize>() {
-        Ok(num) => {
-            let new_num = num + index + 1;
-            format!("{}{:0width$}", prefix, new_num, width = num_part.len())
+fn parse_template(pattern: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = pattern;
+    while !rest.is_empty() {
+        let Some(open) = rest.find('{') else {
+            if rest.contains('}') {
+                return Err(format!("Unmatched '}}' in '{}'", pattern));
+            }
+            parts.push(Part::Literal(rest.to_string()));
+            break;
+        };
+        let (literal, tail) = rest.split_at(open);
+        if literal.contains('}') {
+            return Err(format!("Unmatched '}}' in '{}'", pattern));
         }
-        Err(_) => {
-            // If num_part isn't a valid number, use prefix and append index + 1 without padding
-            format!("{}{}", prefix, index + 1)
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal.to_string()));
+        }
+        let close = tail
+            .find('}')
+            .ok_or_else(|| format!("Unclosed '{{' in '{}'", pattern))?;
+        parts.push(parse_placeholder(&tail[1..close])?);
+        rest = &tail[close + 1..];
+    }
+
+    match parts
+        .iter()
+        .filter(|p| matches!(p, Part::Seq { .. }))
+        .count()
+    {
+        1 => Ok(parts),
+        0 => Err(format!("'{}' needs a {{SEQ}} placeholder", pattern)),
+        _ => Err(format!(
+            "'{}' has more than one {{SEQ}} placeholder",
+            pattern
+        )),
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+    let placeholder = placeholder.trim();
+    if let Some(width) = placeholder.strip_prefix("SEQ") {
+        let width = match width.strip_prefix(':') {
+            Some(width) => width
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid {{SEQ}} width in '{{{}}}'", placeholder))?,
+            None if width.is_empty() => 0,
+            None => return Err(format!("Unknown placeholder '{{{}}}'", placeholder)),
+        };
+        return Ok(Part::Seq { width, start: 0 });
+    }
+    if placeholder == "YEAR" {
+        return Ok(Part::Year);
+    }
+
+    let name_end = placeholder
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(placeholder.len());
+    let (name, len) = placeholder.split_at(name_end);
+    let field = match name {
+        "ORG" => TemplateField::Org,
+        "ZONE" => TemplateField::Zone,
+        "CLUSTER" => TemplateField::Cluster,
+        "CITY" => TemplateField::City,
+        _ => {
+            return Err(format!(
+                "Unknown placeholder '{{{}}}', expected ORG, ZONE, CLUSTER, CITY, YEAR or SEQ",
+                placeholder
+            ));
         }
+    };
+    let len = match len {
+        "" => None,
+        len => Some(
+            len.parse::<usize>()
+                .ok()
+                .filter(|&len| len > 0)
+                .ok_or_else(|| format!("Invalid length in '{{{}}}'", placeholder))?,
+        ),
+    };
+    Ok(Part::Field { field, len })
+}
+
+/// A field value as it appears in an ID: uppercase letters and digits only,
+/// accents folded, cut to `len` characters.
+fn field_text(value: &str, len: Option<usize>) -> String {
+    let text: String = normalize_address(value)
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_uppercase)
+        .collect();
+    match len {
+        Some(len) => text.chars().take(len).collect(),
+        None => text,
     }
 }
 
+/// Matches `stop_id` against `parts`, returning the 0-based sequence index
+/// it encodes, or `None` when it does not have the template's shape.
+fn match_parts(parts: &[Part], stop_id: &str) -> Option<usize> {
+    let Some((part, rest)) = parts.split_first() else {
+        // The index is filled in by the Seq part on the way back
+        return stop_id.is_empty().then_some(0);
+    };
+    let run = |allowed: fn(&char) -> bool| {
+        stop_id
+            .char_indices()
+            .find(|(_, c)| !allowed(c))
+            .map_or(stop_id.len(), |(i, _)| i)
+    };
+    // Byte lengths this part could take up at the start of `stop_id`
+    let candidates: Vec<usize> = match part {
+        Part::Literal(text) => match stop_id.starts_with(text.as_str()) {
+            true => vec![text.len()],
+            false => vec![],
+        },
+        Part::Year => match run(char::is_ascii_digit) >= 4 {
+            true => vec![4],
+            false => vec![],
+        },
+        Part::Seq { width, .. } => (*width.max(&1)..=run(char::is_ascii_digit)).collect(),
+        Part::Field { len, .. } => {
+            let end = run(|c| c.is_alphanumeric() && !c.is_lowercase());
+            let ends = stop_id[..end].char_indices().map(|(i, c)| i + c.len_utf8());
+            std::iter::once(0)
+                .chain(ends)
+                .take(len.map_or(usize::MAX, |len| len + 1))
+                .collect()
+        }
+    };
+    candidates.into_iter().find_map(|end| {
+        let index = match_parts(rest, &stop_id[end..])?;
+        match part {
+            Part::Seq { start, .. } => {
+                let number: usize = stop_id[..end].parse().ok()?;
+                number.checked_sub(start + 1)
+            }
+            _ => Some(index),
+        }
+    })
+}
+
+/// Current year in UTC.
+fn current_year() -> i64 {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+    // Civil-from-days (H. Hinnant), years starting in March
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    yoe + era * 400 + i64::from(month <= 2)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn generate_stop_id(pattern: &str, index: usize) -> String {
+        StopIdTemplate::parse(pattern)
+            .unwrap()
+            .render(|_| String::new(), index)
+    }
+
+    fn matches_stop_id_pattern(pattern: &str, stop_id: &str) -> bool {
+        StopIdTemplate::parse(pattern).unwrap().matches(stop_id)
+    }
+
     #[test]
     fn test_basic_increment() {
         assert_eq!(generate_stop_id("ST000000", 0), "ST000001");
@@ -56,4 +530,109 @@ mod tests {
         assert_eq!(generate_stop_id("", 0), "1");
         assert_eq!(generate_stop_id("", 5), "6");
     }
+
+    #[test]
+    fn test_matches_stop_id_pattern() {
+        assert!(matches_stop_id_pattern("ST000000", "ST000001"));
+        assert!(matches_stop_id_pattern("ST000000", "ST1000000"));
+        assert!(!matches_stop_id_pattern("ST000000", "ST0001"));
+        assert!(!matches_stop_id_pattern("ST000000", "XT000001"));
+        assert!(!matches_stop_id_pattern("ST000000", "ST00000A"));
+        assert!(matches_stop_id_pattern("STOP", "STOP12"));
+    }
+
+    #[test]
+    fn test_template_sequences_per_group() {
+        let template = StopIdTemplate::parse("{ORG}-{ZONE}-{SEQ:05}").unwrap();
+        let mut generator = template.generator();
+        let value = |zone: &'static str| {
+            move |field| match field {
+                TemplateField::Org => "acme".to_string(),
+                TemplateField::Zone => zone.to_string(),
+                _ => String::new(),
+            }
+        };
+
+        assert_eq!(generator.next_id(value("North 1")), "ACME-NORTH1-00001");
+        assert_eq!(generator.next_id(value("South")), "ACME-SOUTH-00001");
+        assert_eq!(generator.next_id(value("north 1")), "ACME-NORTH1-00002");
+        assert_eq!(
+            template.fields(),
+            vec![TemplateField::Org, TemplateField::Zone]
+        );
+        assert!(template.matches("ACME-NORTH1-00002"));
+        assert!(!template.matches("ACME-NORTH1-002"));
+    }
+
+    #[test]
+    fn test_template_cut_fields_and_year() {
+        let city = StopIdTemplate::parse("{CITY3}{SEQ:04}").unwrap();
+        assert_eq!(city.render(|_| "Bogotá D.C.".to_string(), 0), "BOG0001");
+        assert!(city.matches("BOG0001"));
+        assert!(!city.matches("BOGO0001"));
+        let mut generator = city.generator();
+        generator.reserve("BOG0005", |_| "Bogotá".to_string(), true);
+        assert_eq!(generator.next_id(|_| "Bogor".to_string()), "BOG0006");
+
+        let year = StopIdTemplate::parse("ST{YEAR}{SEQ:06}").unwrap();
+        let id = year.render(|_| String::new(), 41);
+        assert_eq!(id, format!("ST{}000042", current_year()));
+        assert!(year.matches("ST2024000001"));
+        assert!(current_year() >= 2024);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(StopIdTemplate::parse("{ZONE}-").is_err());
+        assert!(StopIdTemplate::parse("{SEQ}{SEQ}").is_err());
+        assert!(StopIdTemplate::parse("{STREET}{SEQ}").is_err());
+        assert!(StopIdTemplate::parse("{SEQ:x}").is_err());
+        assert!(StopIdTemplate::parse("{SEQ").is_err());
+        assert!(StopIdTemplate::parse("SEQ}").is_err());
+    }
+
+    #[test]
+    fn test_check_digits() {
+        assert_eq!(CheckDigit::Luhn.compute("7992739871"), '3');
+        assert_eq!(CheckDigit::Damm.compute("572"), '4');
+        assert_eq!(CheckDigit::Mod11.compute("0"), '0');
+        assert_eq!(CheckDigit::Mod11.compute("5"), '1');
+        for check_digit in [CheckDigit::Luhn, CheckDigit::Mod11, CheckDigit::Damm] {
+            let id = check_digit.append("ST000123");
+            assert!(check_digit.verify(&id), "{:?} {}", check_digit, id);
+            assert!(!check_digit.verify(&id.replace("123", "128")));
+            assert!(!check_digit.verify(&id.replace("123", "213")));
+            assert!(!check_digit.verify(""));
+        }
+    }
+
+    #[test]
+    fn test_template_with_check_digit() {
+        let template = StopIdTemplate::parse("ST000000")
+            .unwrap()
+            .with_check_digit(Some(CheckDigit::Damm));
+        let id = template.render(|_| String::new(), 0);
+        assert_eq!(id, CheckDigit::Damm.append("ST000001"));
+        assert!(template.matches(&id));
+        assert!(!template.matches("ST000001"));
+    }
+
+    #[test]
+    fn test_generator_skips_taken_and_continues_after_max() {
+        let template = StopIdTemplate::parse("{ZONE}-{SEQ:03}").unwrap();
+        let zone = |zone: &'static str| move |_| zone.to_string();
+        assert_eq!(template.sequence_index("NORTH-007"), Some(6));
+        assert_eq!(template.sequence_index("NORTH-7"), None);
+
+        let mut generator = template.generator();
+        generator.reserve("NORTH-002", zone("North"), false);
+        assert_eq!(generator.next_id(zone("North")), "NORTH-001");
+        assert_eq!(generator.next_id(zone("North")), "NORTH-003");
+
+        let mut generator = template.generator();
+        generator.reserve("NORTH-007", zone("North"), true);
+        generator.reserve("SOUTH-004", zone("North"), true);
+        assert_eq!(generator.next_id(zone("North")), "NORTH-008");
+        assert_eq!(generator.next_id(zone("South")), "SOUTH-001");
+    }
 }
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use super::geojson::Area;
+
+/// Mean Earth radius in meters.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Great-circle distance in meters between two (latitude, longitude) points.
+pub fn haversine_m(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = lon2 - lon1;
+
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+/// Whether a point is 0, 0 ("null island"), the placeholder many systems
+/// write for a missing position.
+pub fn is_null_island(point: (f64, f64)) -> bool {
+    point.0 == 0.0 && point.1 == 0.0
+}
+
+/// Whether a (latitude, longitude) pair is within WGS84 bounds.
+pub fn is_valid_coordinate(point: (f64, f64)) -> bool {
+    (-90.0..=90.0).contains(&point.0) && (-180.0..=180.0).contains(&point.1)
+}
+
+/// Whether a (latitude, longitude) pair makes more sense the other way round:
+/// it is out of range but valid when swapped, or falls outside `bbox` while
+/// the swapped point falls inside.
+pub fn looks_swapped(point: (f64, f64), bbox: Option<&BoundingBox>) -> bool {
+    let swapped = (point.1, point.0);
+    if !is_valid_coordinate(point) {
+        return is_valid_coordinate(swapped);
+    }
+    match bbox {
+        Some(bbox) => !bbox.contains(point) && bbox.contains(swapped),
+        None => false,
+    }
+}
+
+/// Whether `reference` (e.g. the geocoded address) confirms that `point` is
+/// swapped: the swapped point lies within `tolerance_m` of it, the point as
+/// given does not.
+pub fn swap_matches_reference(point: (f64, f64), reference: (f64, f64), tolerance_m: f64) -> bool {
+    let swapped = (point.1, point.0);
+    haversine_m(swapped, reference) <= tolerance_m && haversine_m(point, reference) > tolerance_m
+}
+
+/// An axis-aligned box in degrees, written `minLon,minLat,maxLon,maxLat`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    /// Smallest box around `points`, or `None` when there are none.
+    pub fn around(points: &[(f64, f64)]) -> Option<Self> {
+        let (first, rest) = points.split_first()?;
+        let mut bbox = BoundingBox {
+            min_lon: first.1,
+            min_lat: first.0,
+            max_lon: first.1,
+            max_lat: first.0,
+        };
+        for &(lat, lon) in rest {
+            bbox.min_lon = bbox.min_lon.min(lon);
+            bbox.min_lat = bbox.min_lat.min(lat);
+            bbox.max_lon = bbox.max_lon.max(lon);
+            bbox.max_lat = bbox.max_lat.max(lat);
+        }
+        Some(bbox)
+    }
+
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        (self.min_lat..=self.max_lat).contains(&point.0)
+            && (self.min_lon..=self.max_lon).contains(&point.1)
+    }
+}
+
+impl std::str::FromStr for BoundingBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid bbox '{}': {}", s, e))?;
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err(format!(
+                "Invalid bbox '{}': expected minLon,minLat,maxLon,maxLat",
+                s
+            ));
+        };
+        if min_lon > max_lon || min_lat > max_lat {
+            return Err(format!("Invalid bbox '{}': minimum exceeds maximum", s));
+        }
+        Ok(BoundingBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        })
+    }
+}
+
+/// Parses a `lat,lon` point.
+pub fn parse_point(s: &str) -> Result<(f64, f64), String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid point '{}': {}", s, e))?;
+    match values[..] {
+        [lat, lon] if is_valid_coordinate((lat, lon)) => Ok((lat, lon)),
+        [_, _] => Err(format!("Invalid point '{}': out of range", s)),
+        _ => Err(format!("Invalid point '{}': expected lat,lon", s)),
+    }
+}
+
+/// Parses a distance such as `2km`, `500m` or `500` (meters) into meters.
+pub fn parse_distance_m(s: &str) -> Result<f64, String> {
+    let s = s.trim().to_lowercase();
+    let (number, factor) = match s.strip_suffix("km") {
+        Some(number) => (number, 1_000.0),
+        None => (s.strip_suffix('m').unwrap_or(&s), 1.0),
+    };
+    match number.trim().parse::<f64>() {
+        Ok(value) if value >= 0.0 => Ok(value * factor),
+        _ => Err(format!(
+            "Invalid distance '{}': expected e.g. 2km or 500m",
+            s
+        )),
+    }
+}
+
+/// Position of a point along a Hilbert curve of order `order` filling
+/// `bbox`. Points close on the curve are close on the map, so sorting by this
+/// index keeps neighbours together.
+pub fn hilbert_index(point: (f64, f64), bbox: &BoundingBox, order: u32) -> u64 {
+    let side = 1u64 << order;
+    let scale = |value: f64, min: f64, max: f64| match max > min {
+        true => (((value - min) / (max - min)) * (side - 1) as f64).round() as u64,
+        false => 0,
+    };
+    let mut x = scale(point.1, bbox.min_lon, bbox.max_lon).min(side - 1);
+    let mut y = scale(point.0, bbox.min_lat, bbox.max_lat).min(side - 1);
+
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+/// Buckets (latitude, longitude) points into cells at least `radius_m` wide,
+/// so finding the points within `radius_m` of one only looks at the 3x3
+/// surrounding cells.
+pub struct PointGrid<'a> {
+    points: &'a [(f64, f64)],
+    radius_m: f64,
+    lat_cell: f64,
+    lon_cell: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl<'a> PointGrid<'a> {
+    pub fn new(points: &'a [(f64, f64)], radius_m: f64) -> Self {
+        // Cells are sized for the widest longitude span, at the latitude
+        // farthest from the equator
+        let max_lat = points
+            .iter()
+            .map(|(lat, _)| lat.abs())
+            .fold(0.0, f64::max)
+            .min(89.0);
+        let lat_cell = radius_m.max(1.0) / 111_320.0;
+        let lon_cell = lat_cell / max_lat.to_radians().cos();
+        let mut grid = PointGrid {
+            points,
+            radius_m,
+            lat_cell,
+            lon_cell,
+            cells: HashMap::new(),
+        };
+        for (i, &point) in points.iter().enumerate() {
+            grid.cells.entry(grid.cell(point)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn cell(&self, (lat, lon): (f64, f64)) -> (i64, i64) {
+        (
+            (lat / self.lat_cell).floor() as i64,
+            (lon / self.lon_cell).floor() as i64,
+        )
+    }
+
+    /// Indices of the points within the radius of point `i`, `i` included.
+    pub fn within(&self, i: usize) -> Vec<usize> {
+        let point = self.points[i];
+        let cell = self.cell(point);
+        let mut found = Vec::new();
+        for dlat in -1..=1 {
+            for dlon in -1..=1 {
+                let Some(others) = self.cells.get(&(cell.0 + dlat, cell.1 + dlon)) else {
+                    continue;
+                };
+                found.extend(
+                    others
+                        .iter()
+                        .filter(|&&j| haversine_m(point, self.points[j]) <= self.radius_m),
+                );
+            }
+        }
+        found
+    }
+}
+
+/// Client-side spatial filter. A point must satisfy every filter that is set;
+/// with no filter set, everything matches, including stops without coordinates.
+#[derive(Debug, Default)]
+pub struct SpatialFilter {
+    pub bbox: Option<BoundingBox>,
+    /// Center point and radius in meters.
+    pub near: Option<((f64, f64), f64)>,
+    /// The point must lie in at least one of these areas.
+    pub within: Vec<Area>,
+}
+
+impl SpatialFilter {
+    pub fn is_empty(&self) -> bool {
+        self.bbox.is_none() && self.near.is_none() && self.within.is_empty()
+    }
+
+    pub fn matches(&self, point: Option<(f64, f64)>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(point) = point else {
+            return false;
+        };
+        self.bbox.is_none_or(|bbox| bbox.contains(point))
+            && self
+                .near
+                .is_none_or(|(center, radius_m)| haversine_m(center, point) <= radius_m)
+            && (self.within.is_empty() || self.within.iter().any(|area| area.contains(point)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_zero() {
+        assert_eq!(haversine_m((4.6, -74.1), (4.6, -74.1)), 0.0);
+    }
+
+    #[test]
+    fn test_haversine_known_distance() {
+        // Paris to London is roughly 344 km
+        let d = haversine_m((48.8566, 2.3522), (51.5074, -0.1278));
+        assert!((d - 343_500.0).abs() < 1_500.0, "{}", d);
+    }
+
+    #[test]
+    fn test_is_valid_coordinate() {
+        assert!(is_valid_coordinate((4.6, -74.1)));
+        assert!(!is_valid_coordinate((104.6, -74.1)));
+        assert!(!is_valid_coordinate((91.0, 0.0)));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let bbox: BoundingBox = "-74.3,4.4,-73.9,4.9".parse().unwrap();
+        assert!(bbox.contains((4.6, -74.1)));
+        assert!(!bbox.contains((-74.1, 4.6)));
+        assert!("-74.3,4.4,-73.9".parse::<BoundingBox>().is_err());
+        assert!("-73.9,4.4,-74.3,4.9".parse::<BoundingBox>().is_err());
+    }
+
+    #[test]
+    fn test_looks_swapped() {
+        let bbox: BoundingBox = "-74.3,4.4,-73.9,4.9".parse().unwrap();
+        assert!(looks_swapped((-74.1, 4.6), Some(&bbox)));
+        assert!(!looks_swapped((4.6, -74.1), Some(&bbox)));
+        // In range and no region to compare against
+        assert!(!looks_swapped((-74.1, 4.6), None));
+        assert!(looks_swapped((151.2, -33.9), None));
+    }
+
+    #[test]
+    fn test_swap_matches_reference() {
+        assert!(swap_matches_reference(
+            (-74.1, 4.6),
+            (4.6001, -74.1001),
+            1_000.0
+        ));
+        assert!(!swap_matches_reference(
+            (4.6, -74.1),
+            (4.6001, -74.1001),
+            1_000.0
+        ));
+    }
+
+    #[test]
+    fn test_parse_distance() {
+        assert_eq!(parse_distance_m("2km"), Ok(2_000.0));
+        assert_eq!(parse_distance_m("500m"), Ok(500.0));
+        assert_eq!(parse_distance_m("1.5 KM"), Ok(1_500.0));
+        assert_eq!(parse_distance_m("250"), Ok(250.0));
+        assert!(parse_distance_m("far").is_err());
+    }
+
+    #[test]
+    fn test_spatial_filter() {
+        let filter = SpatialFilter {
+            bbox: Some("-74.3,4.4,-73.9,4.9".parse().unwrap()),
+            near: Some(((4.6, -74.1), 2_000.0)),
+            ..Default::default()
+        };
+        assert!(filter.matches(Some((4.61, -74.1))));
+        assert!(!filter.matches(Some((4.7, -74.1))));
+        assert!(!filter.matches(None));
+        assert!(SpatialFilter::default().matches(None));
+    }
+
+    #[test]
+    fn test_point_grid_within() {
+        let points = [
+            (4.6, -74.1),
+            // About 11 m north, then about 110 m north of the first
+            (4.6001, -74.1),
+            (4.601, -74.1),
+        ];
+        let grid = PointGrid::new(&points, 25.0);
+        let mut found = grid.within(0);
+        found.sort_unstable();
+        assert_eq!(found, vec![0, 1]);
+        assert_eq!(grid.within(2), vec![2]);
+    }
+
+    #[test]
+    fn test_hilbert_index_keeps_neighbours_close() {
+        let bbox = BoundingBox {
+            min_lon: 0.0,
+            min_lat: 0.0,
+            max_lon: 3.0,
+            max_lat: 3.0,
+        };
+        // The order 2 curve over a 4x4 grid runs from the south-west corner
+        // to the south-east one
+        assert_eq!(hilbert_index((0.0, 0.0), &bbox, 2), 0);
+        assert_eq!(hilbert_index((0.0, 1.0), &bbox, 2), 1);
+        assert_eq!(hilbert_index((1.0, 1.0), &bbox, 2), 2);
+        assert_eq!(hilbert_index((0.0, 3.0), &bbox, 2), 15);
+    }
+}
@@ -0,0 +1,161 @@
+use std::error::Error;
+
+use serde_json::{Map, Value};
+
+/// A ring of (latitude, longitude) points; the last point may repeat the first.
+pub type Ring = Vec<(f64, f64)>;
+
+/// A polygon feature from a GeoJSON file: one or more polygons, each an
+/// outer ring followed by its holes, plus the feature's properties.
+#[derive(Debug, Clone, Default)]
+pub struct Area {
+    pub properties: Map<String, Value>,
+    pub polygons: Vec<Vec<Ring>>,
+}
+
+impl Area {
+    /// Whether a (latitude, longitude) point is inside the area. Points on an
+    /// edge may fall either way.
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        self.polygons.iter().any(|rings| match rings.split_first() {
+            Some((outer, holes)) => {
+                ring_contains(outer, point) && !holes.iter().any(|hole| ring_contains(hole, point))
+            }
+            None => false,
+        })
+    }
+
+    /// A property as text, for numbers as well as strings.
+    pub fn property(&self, key: &str) -> Option<String> {
+        match self.properties.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// Even-odd ray casting in the (longitude, latitude) plane.
+fn ring_contains(ring: &[(f64, f64)], point: (f64, f64)) -> bool {
+    let (lat, lon) = point;
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let (lat_i, lon_i) = ring[i];
+        let (lat_j, lon_j) = ring[j];
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Reads the Polygon and MultiPolygon features of a GeoJSON file. A bare
+/// geometry or a single Feature is read as one area.
+pub fn read_areas(path: &str) -> Result<Vec<Area>, Box<dyn Error>> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let json: Value =
+        serde_json::from_str(&text).map_err(|e| format!("Invalid GeoJSON '{}': {}", path, e))?;
+    let areas = parse_areas(&json)?;
+    if areas.is_empty() {
+        return Err(format!("No Polygon or MultiPolygon features in '{}'", path).into());
+    }
+    Ok(areas)
+}
+
+pub fn parse_areas(json: &Value) -> Result<Vec<Area>, Box<dyn Error>> {
+    let features = match json["type"].as_str() {
+        Some("FeatureCollection") => json["features"]
+            .as_array()
+            .ok_or("FeatureCollection without features")?
+            .iter()
+            .collect(),
+        _ => vec![json],
+    };
+
+    let mut areas = Vec::new();
+    for feature in features {
+        let (geometry, properties) = match feature["type"].as_str() {
+            Some("Feature") => (
+                &feature["geometry"],
+                feature["properties"]
+                    .as_object()
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            _ => (feature, Map::new()),
+        };
+        let polygons = match geometry["type"].as_str() {
+            Some("Polygon") => vec![parse_polygon(&geometry["coordinates"])?],
+            Some("MultiPolygon") => geometry["coordinates"]
+                .as_array()
+                .ok_or("MultiPolygon without coordinates")?
+                .iter()
+                .map(parse_polygon)
+                .collect::<Result<_, _>>()?,
+            _ => continue,
+        };
+        areas.push(Area {
+            properties,
+            polygons,
+        });
+    }
+    Ok(areas)
+}
+
+fn parse_polygon(coordinates: &Value) -> Result<Vec<Ring>, Box<dyn Error>> {
+    coordinates
+        .as_array()
+        .ok_or("Polygon without coordinates")?
+        .iter()
+        .map(|ring| {
+            ring.as_array()
+                .ok_or("Invalid polygon ring")?
+                .iter()
+                .map(
+                    |position| match (position[0].as_f64(), position[1].as_f64()) {
+                        (Some(lon), Some(lat)) => Ok((lat, lon)),
+                        _ => Err("Invalid polygon position".into()),
+                    },
+                )
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_area_contains_with_hole() {
+        let json = json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "properties": { "name": "Zone 3", "id": 3 },
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [
+                        [[-74.2, 4.5], [-74.0, 4.5], [-74.0, 4.7], [-74.2, 4.7], [-74.2, 4.5]],
+                        [[-74.12, 4.58], [-74.08, 4.58], [-74.08, 4.62], [-74.12, 4.62], [-74.12, 4.58]]
+                    ]
+                }
+            }]
+        });
+
+        let areas = parse_areas(&json).unwrap();
+
+        assert_eq!(areas.len(), 1);
+        assert_eq!(areas[0].property("name").as_deref(), Some("Zone 3"));
+        assert_eq!(areas[0].property("id").as_deref(), Some("3"));
+        assert!(areas[0].contains((4.52, -74.15)));
+        assert!(!areas[0].contains((4.6, -74.1)));
+        assert!(!areas[0].contains((4.8, -74.1)));
+    }
+}
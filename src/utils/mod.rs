@@ -1,2 +1,9 @@
+pub mod address;
 pub mod generate_id;
+pub mod geo;
+pub mod geojson;
+pub mod osm_pbf;
+pub mod prompt;
+pub mod redact;
 pub mod xlsx;
+pub mod xlsx_patch;
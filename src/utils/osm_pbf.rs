@@ -0,0 +1,173 @@
+//! Road network of OpenStreetMap PBF extracts. The file is read twice: once
+//! for the road ways, then for just the nodes those ways reference, so
+//! neither the file nor all of its nodes are held in memory.
+
+use std::{collections::HashMap, error::Error};
+
+use osmpbf::{Element, ElementReader};
+
+/// `highway` values routable by car. Footways, paths, cycleways and the like
+/// are left out so stops do not snap to a pedestrian street.
+const ROAD_HIGHWAYS: [&str; 16] = [
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+    "road",
+    "busway",
+];
+
+/// A road as a line of (latitude, longitude) points.
+pub type Polyline = Vec<(f64, f64)>;
+
+/// Road ways of an extract.
+pub fn read_roads(path: &str) -> Result<Vec<Polyline>, Box<dyn Error>> {
+    let reader = |path: &str| {
+        ElementReader::from_path(path).map_err(|e| format!("Failed to read '{}': {}", path, e))
+    };
+
+    let mut ways: Vec<Vec<i64>> = Vec::new();
+    reader(path)?.for_each(|element| {
+        if let Element::Way(way) = element
+            && way
+                .tags()
+                .any(|(key, value)| key == "highway" && ROAD_HIGHWAYS.contains(&value))
+        {
+            ways.push(way.refs().collect());
+        }
+    })?;
+
+    let mut nodes: HashMap<i64, Option<(f64, f64)>> =
+        ways.iter().flatten().map(|&id| (id, None)).collect();
+    reader(path)?.for_each(|element| {
+        let (id, point) = match element {
+            Element::Node(node) => (node.id(), (node.lat(), node.lon())),
+            Element::DenseNode(node) => (node.id(), (node.lat(), node.lon())),
+            _ => return,
+        };
+        if let Some(slot) = nodes.get_mut(&id) {
+            *slot = Some(point);
+        }
+    })?;
+
+    Ok(ways
+        .into_iter()
+        .map(|refs| {
+            refs.iter()
+                .filter_map(|id| nodes.get(id).copied().flatten())
+                .collect::<Vec<_>>()
+        })
+        .filter(|line| line.len() >= 2)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut v: u64, out: &mut Vec<u8>) {
+        while v >= 0x80 {
+            out.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn bytes_field(number: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(number << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn uint_field(number: u64, values: &[u64], out: &mut Vec<u8>) {
+        let mut packed = Vec::new();
+        for &v in values {
+            varint(v, &mut packed);
+        }
+        bytes_field(number, &packed, out);
+    }
+
+    /// Delta- and zigzag-encoded, as for ids, refs and coordinates.
+    fn packed_field(number: u64, values: &[i64], out: &mut Vec<u8>) {
+        let mut packed = Vec::new();
+        let mut previous = 0;
+        for &v in values {
+            let delta = v - previous;
+            previous = v;
+            varint(((delta << 1) ^ (delta >> 63)) as u64, &mut packed);
+        }
+        bytes_field(number, &packed, out);
+    }
+
+    /// An uncompressed blob with its header.
+    fn blob(blob_type: &str, contents: &[u8], out: &mut Vec<u8>) {
+        let mut blob = Vec::new();
+        bytes_field(1, contents, &mut blob);
+
+        let mut header = Vec::new();
+        bytes_field(1, blob_type.as_bytes(), &mut header);
+        varint(3 << 3, &mut header);
+        varint(blob.len() as u64, &mut header);
+
+        out.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&blob);
+    }
+
+    #[test]
+    fn test_read_roads() {
+        let mut strings = Vec::new();
+        for s in ["", "highway", "residential", "footway"] {
+            bytes_field(1, s.as_bytes(), &mut strings);
+        }
+        let mut dense = Vec::new();
+        packed_field(1, &[1, 2, 3], &mut dense);
+        packed_field(8, &[46_000_000, 46_001_000, 46_002_000], &mut dense);
+        packed_field(9, &[-741_000_000, -741_000_000, -741_001_000], &mut dense);
+        let mut road = Vec::new();
+        varint(1 << 3, &mut road);
+        varint(10, &mut road);
+        uint_field(2, &[1], &mut road);
+        uint_field(3, &[2], &mut road);
+        packed_field(8, &[1, 2], &mut road);
+        let mut footway = Vec::new();
+        varint(1 << 3, &mut footway);
+        varint(11, &mut footway);
+        uint_field(2, &[1], &mut footway);
+        uint_field(3, &[3], &mut footway);
+        packed_field(8, &[2, 3], &mut footway);
+        let mut group = Vec::new();
+        bytes_field(2, &dense, &mut group);
+        bytes_field(3, &road, &mut group);
+        bytes_field(3, &footway, &mut group);
+        let mut block = Vec::new();
+        bytes_field(1, &strings, &mut block);
+        bytes_field(2, &group, &mut block);
+
+        let mut file = Vec::new();
+        blob("OSMHeader", b"", &mut file);
+        blob("OSMData", &block, &mut file);
+
+        let path = std::env::temp_dir().join("veza_cli_roads.osm.pbf");
+        std::fs::write(&path, file).unwrap();
+        let roads = read_roads(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+        let roads = roads.unwrap();
+
+        assert_eq!(roads.len(), 1);
+        assert_eq!(roads[0].len(), 2);
+        assert!((roads[0][0].0 - 4.6).abs() < 1e-9);
+        assert!((roads[0][0].1 + 74.1).abs() < 1e-9);
+        assert!((roads[0][1].0 - 4.6001).abs() < 1e-9);
+    }
+}
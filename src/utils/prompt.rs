@@ -0,0 +1,36 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use secrecy::{SecretString, zeroize::Zeroize};
+
+/// Asks a yes/no question on stdin. Anything other than "y" or "yes" is a no.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Asks for a secret without echoing it when stdin is a terminal; otherwise
+/// reads one line, so tokens can be piped in.
+pub fn secret(question: &str) -> io::Result<SecretString> {
+    print!("{}: ", question);
+    io::stdout().flush()?;
+
+    let mut answer = match io::stdin().is_terminal() {
+        true => rpassword::read_password()?,
+        false => {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            println!();
+            line
+        }
+    };
+    let secret = SecretString::from(answer.trim());
+    answer.zeroize();
+    Ok(secret)
+}
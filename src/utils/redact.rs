@@ -0,0 +1,186 @@
+use std::{
+    io::{self, Write},
+    sync::RwLock,
+};
+
+use reqwest::{Url, header::HeaderMap};
+use secrecy::{ExposeSecret, SecretString};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Shown in place of a secret.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Query parameters whose values are never logged.
+const SECRET_PARAMS: &[&str] = &[
+    "access_token",
+    "api_key",
+    "apikey",
+    "key",
+    "password",
+    "secret",
+    "token",
+];
+
+/// Headers whose values are never logged.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// Values shorter than this are not masked, they would hide ordinary text.
+const MIN_SECRET_LEN: usize = 6;
+
+/// Secret values masked by [`Masking`] wherever they appear in log output.
+static SECRETS: RwLock<Vec<SecretString>> = RwLock::new(Vec::new());
+
+/// Masks `secret` in all log output from now on.
+pub fn register_secret(secret: &SecretString) {
+    let value = secret.expose_secret();
+    if value.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(|e| e.into_inner());
+    if !secrets.iter().any(|known| known.expose_secret() == value) {
+        secrets.push(secret.clone());
+    }
+}
+
+/// `text` with every registered secret replaced.
+pub fn mask(text: &str) -> String {
+    let secrets = SECRETS.read().unwrap_or_else(|e| e.into_inner());
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret.expose_secret(), REDACTED)
+    })
+}
+
+/// `url` with the values of token-like query parameters and any password
+/// replaced, for use in log and error messages.
+pub fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = match SECRET_PARAMS.contains(&name.to_ascii_lowercase().as_str()) {
+                    true => REDACTED.to_string(),
+                    false => value.into_owned(),
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// `headers` as name/value pairs with credentials replaced.
+pub fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match value.is_sensitive() || SECRET_HEADERS.contains(&name.as_str()) {
+                true => REDACTED.to_string(),
+                false => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Wraps a tracing writer so registered secrets never reach it.
+pub struct Masking<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Masking<M> {
+    type Writer = MaskingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MaskingWriter(self.0.make_writer())
+    }
+}
+
+/// Writer of [`Masking`]. Each formatted event arrives in a single write, so
+/// a secret is never split between two calls.
+pub struct MaskingWriter<W>(W);
+
+impl<W: Write> Write for MaskingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(mask(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, HeaderValue};
+    use std::sync::{Arc, Mutex};
+    use tracing::{error, warn};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_redact_url_and_headers() {
+        let url = Url::parse(
+            "https://user:pw@api.example.com/forward?q=Main+St&access_token=pk.abc123456",
+        )
+        .unwrap();
+        let redacted = redact_url(&url);
+        assert!(!redacted.contains("pk.abc123456"));
+        assert!(!redacted.contains(":pw@"));
+        assert!(redacted.contains("q=Main+St"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer abc123456"));
+        let redacted = redact_headers(&headers);
+        assert!(redacted.contains(&("authorization".to_string(), REDACTED.to_string())));
+        assert!(redacted.contains(&("content-type".to_string(), "application/json".to_string())));
+    }
+
+    #[test]
+    fn test_no_secret_in_log_output() {
+        let secret = "sk.redact-test-7f3a9c";
+        register_secret(&SecretString::from(secret));
+        let capture = Capture::default();
+        let output = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(Masking(move || capture.clone()))
+            .with_ansi(false)
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let url = format!("https://api.mapbox.com/forward?q=x&access_token={}", secret);
+            warn!("Rate limit hit for {}", url);
+            error!("Request failed: {:?}", format!("Bearer {}", secret));
+        });
+
+        let logged = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+        assert!(!logged.contains(secret));
+        assert!(logged.contains(REDACTED));
+        assert_eq!(mask("short abc"), "short abc");
+    }
+}
@@ -1,11 +1,18 @@
 use std::{collections::HashMap, error::Error, path::Path};
 
-use calamine::{Data, Reader, Xlsx, open_workbook};
-use rust_xlsxwriter::Workbook;
+use calamine::{Data, Range, Reader, open_workbook_auto};
+use rust_xlsxwriter::{ConditionalFormatFormula, Format, Url, Workbook, column_number_to_name};
 use tracing::{info, warn};
 
 use crate::models::traits::Model;
 
+use super::xlsx_patch::{CellValue, SheetPatch, patch_xlsx};
+
+const MAP_HEADER: &str = "Map";
+
+/// Numeric columns shown with six decimals, about 0.1 m.
+const COORDINATE_HEADERS: &[&str] = &["Latitude", "Longtitude"];
+
 pub fn write_xlsx<T: Model>(items: Vec<T>, file_name: &str) -> Result<(), Box<dyn Error>> {
     info!(
         "Exporting {} {}s to {}",
@@ -15,77 +22,553 @@ pub fn write_xlsx<T: Model>(items: Vec<T>, file_name: &str) -> Result<(), Box<dy
     );
 
     let mut workbook = Workbook::new();
+    add_sheet(&mut workbook, None, &items)?;
+
+    workbook.save(file_name)?;
+    info!(
+        "Successfully exported {} {}s to '{}'",
+        items.len(),
+        T::display_name(),
+        file_name
+    );
+
+    Ok(())
+}
+
+/// Adds a formatted worksheet of `items` to `workbook`, for outputs that
+/// combine several sheets.
+pub fn add_sheet<T: Model>(
+    workbook: &mut Workbook,
+    name: Option<&str>,
+    items: &[T],
+) -> Result<(), Box<dyn Error>> {
+    // Output-only columns that no item fills are left out
+    let rows: Vec<Vec<String>> = items
+        .iter()
+        .map(|item| {
+            let mut values = item.to_row();
+            values.extend(item.to_extra_row());
+            values
+        })
+        .collect();
+    let mut headers = T::headers();
+    headers.extend(T::extra_headers());
+    let columns: Vec<usize> = (0..headers.len())
+        .filter(|&col| {
+            col < T::headers().len() || rows.iter().any(|row| !row[col].trim().is_empty())
+        })
+        .collect();
+    let mut headers: Vec<&str> = columns.iter().map(|&col| headers[col]).collect();
+    let has_map_column = items.iter().any(|item| item.map_url().is_some());
+    if has_map_column {
+        headers.push(MAP_HEADER);
+    }
+    let numeric_headers = T::numeric_headers();
+    let numeric_columns: Vec<bool> = headers
+        .iter()
+        .map(|header| numeric_headers.contains(header))
+        .collect();
+
+    let header_format = Format::new().set_bold();
+    let coordinate_format = Format::new().set_num_format("0.000000");
+
     let worksheet = workbook.add_worksheet();
-    for (col, &header) in T::headers().iter().enumerate() {
-        worksheet.write_string(0, col as u16, header)?;
+    if let Some(name) = name {
+        worksheet.set_name(name)?;
+    }
+    for (col, &header) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as u16, header, &header_format)?;
     }
 
-    for (row, item) in items.iter().enumerate() {
+    for (row, (item, values)) in items.iter().zip(rows.iter()).enumerate() {
         let row = row as u32 + 1;
-        for (col, value) in item.to_row().iter().enumerate() {
-            worksheet.write_string(row, col as u16, value)?;
+        for (col, value) in columns.iter().map(|&col| &values[col]).enumerate() {
+            let number = match numeric_columns.get(col) {
+                Some(true) => value.trim().parse::<f64>().ok(),
+                _ => None,
+            };
+            match number {
+                Some(number) if COORDINATE_HEADERS.contains(&headers[col]) => worksheet
+                    .write_number_with_format(row, col as u16, number, &coordinate_format)?,
+                Some(number) => worksheet.write_number(row, col as u16, number)?,
+                None => worksheet.write_string(row, col as u16, value)?,
+            };
+        }
+        if let Some(url) = item.map_url() {
+            worksheet.write_url_with_text(
+                row,
+                (headers.len() - 1) as u16,
+                Url::new(url),
+                "Open map",
+            )?;
         }
     }
 
-    workbook.save(file_name)?;
+    let last_row = items.len() as u32;
+    let last_col = (headers.len() - 1) as u16;
+    worksheet.set_freeze_panes(1, 0)?;
+    worksheet.autofilter(0, 0, last_row, last_col)?;
+
+    // Highlight rows the model flags, e.g. stops that failed geocoding
+    if let Some((header, values)) = T::highlight_rule()
+        && let Some(col) = headers.iter().position(|h| *h == header)
+        && last_row > 0
+    {
+        let column = column_number_to_name(col as u16);
+        let matches: Vec<String> = values
+            .iter()
+            .map(|value| format!("${}2=\"{}\"", column, value))
+            .collect();
+        let rule = format!("=OR({})", matches.join(","));
+        let highlight = ConditionalFormatFormula::new()
+            .set_rule(rule.as_str())
+            .set_format(
+                Format::new()
+                    .set_background_color("#FFC7CE")
+                    .set_font_color("#9C0006"),
+            );
+        worksheet.add_conditional_format(1, 0, last_row, last_col, &highlight)?;
+    }
+
+    worksheet.autofit();
+    Ok(())
+}
+
+/// Writes items back into a copy of the workbook they were read from.
+///
+/// Only the `update` columns are overwritten in place; `T::extra_headers()`
+/// are written to their existing columns or appended after the header row.
+/// Other cells, sheets and formatting are left untouched.
+pub fn write_back_xlsx<T: Model>(
+    source: &str,
+    output: &str,
+    sheets: &[SheetItems<T>],
+    update: &[&str],
+) -> Result<(), Box<dyn Error>> {
+    let extension = Path::new(source)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if extension != "xlsx" && extension != "xlsm" {
+        return Err(format!(
+            "Preserving the source workbook requires an .xlsx or .xlsm file, got '{}'",
+            source
+        )
+        .into());
+    }
+
+    let mut headers = T::headers();
+    headers.extend(T::extra_headers());
+    let numeric_headers = T::numeric_headers();
+
+    let mut patches = Vec::new();
+    for sheet in sheets {
+        let mut patch = SheetPatch::new(&sheet.sheet);
+        let mut next_free_column = sheet.next_free_column;
+        let mut targets: Vec<(usize, u32)> = Vec::new();
+        let rows: Vec<Vec<String>> = sheet
+            .rows
+            .iter()
+            .map(|(_, item)| {
+                let mut values = item.to_row();
+                values.extend(item.to_extra_row());
+                values
+            })
+            .collect();
+        for (i, header) in headers.iter().enumerate() {
+            let is_extra = i >= T::headers().len();
+            if !is_extra && !update.contains(header) {
+                continue;
+            }
+            // Output-only columns that no item fills are left out
+            if is_extra && rows.iter().all(|values| values[i].trim().is_empty()) {
+                continue;
+            }
+            let col = match sheet.columns.get(*header) {
+                Some(&col) => col,
+                None => {
+                    let col = next_free_column;
+                    next_free_column += 1;
+                    patch.set(sheet.header_row, col, CellValue::Text(header.to_string()));
+                    col
+                }
+            };
+            targets.push((i, col));
+        }
+
+        for ((row, _), values) in sheet.rows.iter().zip(rows.iter()) {
+            for &(i, col) in targets.iter() {
+                let value = &values[i];
+                let number = match numeric_headers.contains(&headers[i]) {
+                    true => value.trim().parse::<f64>().ok(),
+                    false => None,
+                };
+                let cell = match number {
+                    Some(number) => CellValue::Number(number),
+                    None => CellValue::Text(value.clone()),
+                };
+                patch.set(*row, col, cell);
+            }
+        }
+        patches.push(patch);
+    }
+
+    patch_xlsx(source, output, &patches)?;
     info!(
-        "Successfully exported {} {}s to '{}'",
-        items.len(),
+        "Wrote {} {}s back into a copy of '{}' at '{}'",
+        sheets.iter().map(|sheet| sheet.rows.len()).sum::<usize>(),
         T::display_name(),
-        file_name
+        source,
+        output
     );
 
     Ok(())
 }
 
-pub fn read_xlsx<T: Model + FromExcelRow>(file_path: &str) -> Result<Vec<T>, Box<dyn Error>> {
+/// Which worksheet(s) to read and where the header row sits.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Sheet name or zero-based index. Defaults to the first sheet.
+    pub sheet: Option<String>,
+    /// Read every sheet that contains the expected headers.
+    pub all_sheets: bool,
+    /// 1-based row number of the header row.
+    pub header_row: u32,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            sheet: None,
+            all_sheets: false,
+            header_row: 1,
+        }
+    }
+}
+
+/// Items read from one worksheet, with enough layout information to write
+/// values back into the original cells.
+#[derive(Debug)]
+pub struct SheetItems<T> {
+    pub sheet: String,
+    /// 1-based row number of the header row.
+    pub header_row: u32,
+    /// Header name to zero-based absolute column index.
+    pub columns: HashMap<String, u32>,
+    /// First column to the right of the header row.
+    pub next_free_column: u32,
+    /// 1-based row number of each item in the sheet.
+    pub rows: Vec<(u32, T)>,
+}
+
+/// Reads items from an .xlsx, .xlsm, .xls or .ods file.
+pub fn read_xlsx<T: Model + FromExcelRow>(
+    file_path: &str,
+    options: &ReadOptions,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    Ok(read_xlsx_sheets(file_path, options)?
+        .into_iter()
+        .flat_map(|sheet| sheet.rows.into_iter().map(|(_, item)| item))
+        .collect())
+}
+
+/// Like [`read_xlsx`], but keeps the items grouped by sheet with their source
+/// row numbers.
+pub fn read_xlsx_sheets<T: Model + FromExcelRow>(
+    file_path: &str,
+    options: &ReadOptions,
+) -> Result<Vec<SheetItems<T>>, Box<dyn Error>> {
     let path = Path::new(file_path);
-    let mut workbook: Xlsx<_> = open_workbook(path)
+    let mut workbook = open_workbook_auto(path)
         .map_err(|e| format!("Failed  to open Excel file  '{}': {}", file_path, e))?;
 
-    let sheet = workbook
-        .worksheet_range_at(0)
-        .ok_or("No sheets found in Excel file")?
-        .map_err(|e| format!("Failed to read sheet: {}", e))?;
+    let sheet_names = workbook.sheet_names();
+    if sheet_names.is_empty() {
+        return Err("No sheets found in Excel file".into());
+    }
+
+    let mut sheets = Vec::new();
+    if options.all_sheets {
+        for name in sheet_names.iter() {
+            let sheet = workbook
+                .worksheet_range(name)
+                .map_err(|e| format!("Failed to read sheet '{}': {}", name, e))?;
+            match read_sheet::<T>(&sheet, options.header_row, file_path, name) {
+                Ok(sheet_items) => sheets.push(sheet_items),
+                Err(e) => warn!("Skipping sheet '{}': {}", name, e),
+            }
+        }
+    } else {
+        let name = select_sheet(&sheet_names, options.sheet.as_deref())?;
+        let sheet = workbook
+            .worksheet_range(&name)
+            .map_err(|e| format!("Failed to read sheet '{}': {}", name, e))?;
+        sheets.push(read_sheet::<T>(
+            &sheet,
+            options.header_row,
+            file_path,
+            &name,
+        )?);
+    }
+
+    let count: usize = sheets.iter().map(|sheet| sheet.rows.len()).sum();
+    if count == 0 {
+        warn!("No Valid items found  in '{}'", file_path);
+    } else {
+        info!("Read {} items from '{}'", count, file_path);
+    }
+
+    Ok(sheets)
+}
+
+/// Resolves `--sheet` to a sheet name. A value matching a sheet name wins over
+/// an index, so a sheet literally called "2" can still be selected.
+fn select_sheet(sheet_names: &[String], sheet: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let Some(sheet) = sheet else {
+        return Ok(sheet_names[0].clone());
+    };
+
+    if let Some(name) = sheet_names.iter().find(|name| name.as_str() == sheet) {
+        return Ok(name.clone());
+    }
 
-    let mut rows = sheet.rows();
+    if let Ok(index) = sheet.parse::<usize>() {
+        return sheet_names.get(index).cloned().ok_or_else(|| {
+            format!(
+                "Sheet index {} out of range, workbook has {} sheet(s)",
+                index,
+                sheet_names.len()
+            )
+            .into()
+        });
+    }
+
+    Err(format!("Sheet '{}' not found. Available: {:?}", sheet, sheet_names).into())
+}
+
+fn read_sheet<T: Model + FromExcelRow>(
+    sheet: &Range<Data>,
+    header_row: u32,
+    file_path: &str,
+    sheet_name: &str,
+) -> Result<SheetItems<T>, Box<dyn Error>> {
+    if header_row == 0 {
+        return Err("Header row is 1-based and must be at least 1".into());
+    }
+
+    // The range starts at the first non-empty cell, so translate the absolute
+    // header row into an offset within the range.
+    let (start_row, start_col) = sheet.start().unwrap_or((0, 0));
+    let header_offset = (header_row - 1)
+        .checked_sub(start_row)
+        .ok_or_else(|| format!("Header row {} is empty in '{}'", header_row, sheet_name))?;
+
+    let mut rows = sheet.rows().skip(header_offset as usize);
     let headers = rows.next().ok_or("No header row found")?;
     let expected_header = T::headers();
 
     let header_map: HashMap<String, usize> = headers
         .iter()
         .enumerate()
-        .map(|(i, h)| (h.to_string(), i))
+        .map(|(i, h)| (h.to_string().trim().to_string(), i))
         .collect();
 
     for expected in expected_header.iter() {
         if !header_map.contains_key(*expected) {
             return Err(format!(
-                "Missing expected header '{}' in '{}'.  Found: {:?}",
+                "Missing expected header '{}' in '{}' sheet '{}'.  Found: {:?}",
                 expected,
                 file_path,
+                sheet_name,
                 header_map.keys().collect::<Vec<_>>()
             )
             .into());
         }
     }
 
+    let columns = header_map
+        .iter()
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, i)| (name.clone(), start_col + *i as u32))
+        .collect();
+    let next_free_column = headers
+        .iter()
+        .rposition(|h| !matches!(h, Data::Empty))
+        .map(|i| start_col + i as u32 + 1)
+        .unwrap_or(start_col);
+
     let mut items = Vec::new();
     for (i, row) in rows.enumerate() {
+        let row_number = header_row + i as u32 + 1;
+        if row.iter().all(|cell| matches!(cell, Data::Empty)) {
+            continue;
+        }
         match T::from_row(row, &header_map) {
-            Ok(item) => items.push(item),
-            Err(e) => warn!("Row {} failed to pasrse: {}", i + 2, e),
+            Ok(item) => items.push((row_number, item)),
+            Err(e) => warn!(
+                "Sheet '{}' row {} failed to pasrse: {}",
+                sheet_name, row_number, e
+            ),
         }
     }
-    if items.is_empty() {
-        warn!("No Valid items found  in '{}'", file_path);
-    } else {
-        info!("Read {} items from '{}'", items.len(), file_path);
-    }
 
-    Ok(items)
+    Ok(SheetItems {
+        sheet: sheet_name.to_string(),
+        header_row,
+        columns,
+        next_free_column,
+        rows: items,
+    })
 }
 
 pub trait FromExcelRow: Sized {
     fn from_row(row: &[Data], header_map: &HashMap<String, usize>) -> Result<Self, Box<dyn Error>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::stop::{GeocodeStatus, Stop};
+
+    fn names() -> Vec<String> {
+        vec!["Cover".to_string(), "Stops".to_string(), "2".to_string()]
+    }
+
+    #[test]
+    fn test_select_sheet_default() {
+        assert_eq!(select_sheet(&names(), None).unwrap(), "Cover");
+    }
+
+    #[test]
+    fn test_select_sheet_by_name_and_index() {
+        assert_eq!(select_sheet(&names(), Some("Stops")).unwrap(), "Stops");
+        assert_eq!(select_sheet(&names(), Some("1")).unwrap(), "Stops");
+        // A sheet named "2" takes precedence over index 2
+        let numbered = ["2", "Cover", "Stops"].map(String::from);
+        assert_eq!(select_sheet(&numbered, Some("2")).unwrap(), "2");
+        assert_eq!(select_sheet(&numbered, Some("1")).unwrap(), "Cover");
+    }
+
+    #[test]
+    fn test_select_sheet_missing() {
+        assert!(select_sheet(&names(), Some("Missing")).is_err());
+        assert!(select_sheet(&names(), Some("7")).is_err());
+    }
+
+    #[test]
+    fn test_write_then_read_round_trip() {
+        let file = std::env::temp_dir().join("veza_cli_round_trip.xlsx");
+        let file = file.to_str().unwrap();
+        let stops = vec![
+            Stop {
+                id: "1".to_string(),
+                stop_id: "ST000001".to_string(),
+                position: "Bogotá, 111611, Colombia".to_string(),
+                latitude: "4.605241".to_string(),
+                longitude: "-74.103439".to_string(),
+                geocode_status: GeocodeStatus::Geocoded,
+                ..Default::default()
+            },
+            Stop {
+                id: "2".to_string(),
+                stop_id: "ST000002".to_string(),
+                position: "Unknown".to_string(),
+                geocode_status: GeocodeStatus::Failed,
+                ..Default::default()
+            },
+        ];
+
+        write_xlsx(stops, file).unwrap();
+        let read: Vec<Stop> = read_xlsx(file, &ReadOptions::default()).unwrap();
+        std::fs::remove_file(file).ok();
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].latitude, "4.605241");
+        assert_eq!(read[0].longitude, "-74.103439");
+        assert_eq!(read[1].position, "Unknown");
+    }
+
+    #[test]
+    fn test_write_back_preserves_other_cells() {
+        let source = std::env::temp_dir().join("veza_cli_write_back_source.xlsx");
+        let output = std::env::temp_dir().join("veza_cli_write_back_output.xlsx");
+        let (source, output) = (source.to_str().unwrap(), output.to_str().unwrap());
+
+        let mut workbook = Workbook::new();
+        let cover = workbook.add_worksheet().set_name("Cover").unwrap();
+        cover.write_string(0, 0, "Customer stops").unwrap();
+        let sheet = workbook.add_worksheet().set_name("Stops").unwrap();
+        sheet.write_string(0, 0, "Title row").unwrap();
+        let headers = ["Notes", "ID", "StopID", "Address", "Latitude", "Longtitude"];
+        for (col, header) in headers.iter().enumerate() {
+            sheet.write_string(1, col as u16, *header).unwrap();
+        }
+        let row = ["call first", "1", "ST000001", "111611", "", ""];
+        for (col, value) in row.iter().enumerate() {
+            sheet.write_string(2, col as u16, *value).unwrap();
+        }
+        workbook.save(source).unwrap();
+
+        let options = ReadOptions {
+            sheet: Some("Stops".to_string()),
+            header_row: 2,
+            ..Default::default()
+        };
+        let mut sheets: Vec<SheetItems<Stop>> = read_xlsx_sheets(source, &options).unwrap();
+        let stop = &mut sheets[0].rows[0].1;
+        stop.position = "Bogotá, 111611, Colombia".to_string();
+        stop.latitude = "4.605241".to_string();
+        stop.longitude = "-74.103439".to_string();
+        stop.geocode_status = GeocodeStatus::Geocoded;
+        stop.geocode_confidence = "high".to_string();
+
+        write_back_xlsx(
+            source,
+            output,
+            &sheets,
+            &["Address", "Latitude", "Longtitude"],
+        )
+        .unwrap();
+
+        let mut workbook = open_workbook_auto(output).unwrap();
+        let cover = workbook.worksheet_range("Cover").unwrap();
+        let sheet = workbook.worksheet_range("Stops").unwrap();
+        std::fs::remove_file(source).ok();
+        std::fs::remove_file(output).ok();
+
+        assert_eq!(
+            cover.get_value((0, 0)),
+            Some(&Data::String("Customer stops".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((0, 0)),
+            Some(&Data::String("Title row".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((1, 6)),
+            Some(&Data::String("Status".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((1, 7)),
+            Some(&Data::String("Confidence".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((2, 0)),
+            Some(&Data::String("call first".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((2, 3)),
+            Some(&Data::String("Bogotá, 111611, Colombia".to_string()))
+        );
+        assert_eq!(sheet.get_value((2, 4)), Some(&Data::Float(4.605241)));
+        assert_eq!(
+            sheet.get_value((2, 6)),
+            Some(&Data::String("OK".to_string()))
+        );
+        assert_eq!(
+            sheet.get_value((2, 7)),
+            Some(&Data::String("high".to_string()))
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::File,
+    io::{Read, Write},
+};
+
+use quick_xml::{
+    Decoder, Reader, Writer,
+    events::{BytesEnd, BytesStart, BytesText, Event},
+};
+use rust_xlsxwriter::column_number_to_name;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+/// A value written into an existing cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CellValue {
+    Text(String),
+    Number(f64),
+}
+
+/// Cell values to write into one worksheet, keyed by 1-based row number and
+/// zero-based column index.
+#[derive(Debug, Default)]
+pub struct SheetPatch {
+    pub sheet: String,
+    pub cells: BTreeMap<u32, BTreeMap<u32, CellValue>>,
+}
+
+impl SheetPatch {
+    pub fn new(sheet: &str) -> Self {
+        SheetPatch {
+            sheet: sheet.to_string(),
+            cells: BTreeMap::new(),
+        }
+    }
+
+    pub fn set(&mut self, row: u32, col: u32, value: CellValue) {
+        self.cells.entry(row).or_default().insert(col, value);
+    }
+}
+
+/// Copies the .xlsx workbook at `source` to `output`, overwriting only the
+/// cells listed in `patches`. Every other part of the package (other sheets,
+/// styles, notes, charts) is copied byte for byte.
+pub fn patch_xlsx(
+    source: &str,
+    output: &str,
+    patches: &[SheetPatch],
+) -> Result<(), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(File::open(source)?)
+        .map_err(|e| format!("'{}' is not an .xlsx workbook: {}", source, e))?;
+    let sheet_paths = sheet_paths(&mut archive)?;
+
+    let mut patched: HashMap<String, Vec<u8>> = HashMap::new();
+    for patch in patches {
+        let path = sheet_paths
+            .get(&patch.sheet)
+            .ok_or_else(|| format!("Sheet '{}' not found in '{}'", patch.sheet, source))?;
+        let xml = read_entry(&mut archive, path)?;
+        patched.insert(path.clone(), patch_sheet_xml(&xml, patch)?);
+    }
+
+    let mut writer = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        match patched.get(file.name()) {
+            Some(xml) => {
+                writer.start_file(file.name(), options)?;
+                writer.write_all(xml)?;
+            }
+            None => writer.raw_copy_file(file)?,
+        }
+    }
+    writer.finish()?;
+
+    Ok(())
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, path: &str) -> Result<String, Box<dyn Error>> {
+    let mut content = String::new();
+    archive
+        .by_name(path)
+        .map_err(|e| format!("Missing '{}' in workbook: {}", path, e))?
+        .read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Maps sheet names to their XML part, following workbook.xml and its rels.
+fn sheet_paths(archive: &mut ZipArchive<File>) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let workbook = read_entry(archive, "xl/workbook.xml")?;
+    let rels = read_entry(archive, "xl/_rels/workbook.xml.rels")?;
+
+    let mut targets = HashMap::new();
+    let mut reader = Reader::from_str(&rels);
+    let decoder = reader.decoder();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                let id = attribute(&e, b"Id", decoder)?;
+                let target = attribute(&e, b"Target", decoder)?;
+                if let (Some(id), Some(target)) = (id, target) {
+                    let path = match target.strip_prefix('/') {
+                        Some(absolute) => absolute.to_string(),
+                        None => format!("xl/{}", target),
+                    };
+                    targets.insert(id, path);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut paths = HashMap::new();
+    let mut reader = Reader::from_str(&workbook);
+    let decoder = reader.decoder();
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"sheet" => {
+                let name = attribute(&e, b"name", decoder)?;
+                // The relationship id is namespaced (`r:id`), so match on local name
+                let rel_id = e
+                    .attributes()
+                    .flatten()
+                    .find(|a| a.key.local_name().as_ref() == b"id")
+                    .map(|a| a.decode_and_unescape_value(decoder).map(|v| v.into_owned()))
+                    .transpose()?;
+                if let (Some(name), Some(rel_id)) = (name, rel_id)
+                    && let Some(path) = targets.get(&rel_id)
+                {
+                    paths.insert(name, path.clone());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(paths)
+}
+
+fn attribute(
+    e: &BytesStart,
+    key: &[u8],
+    decoder: Decoder,
+) -> Result<Option<String>, Box<dyn Error>> {
+    match e.try_get_attribute(key)? {
+        Some(attr) => Ok(Some(attr.decode_and_unescape_value(decoder)?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Parses a cell reference like "AB12" into (1-based row, zero-based column).
+fn parse_cell_ref(cell_ref: &str) -> Option<(u32, u32)> {
+    let letters = cell_ref
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .count();
+    let (col, row) = cell_ref.split_at(letters);
+    if col.is_empty() {
+        return None;
+    }
+    let col = col.chars().fold(0u32, |acc, c| {
+        acc * 26 + (c.to_ascii_uppercase() as u32 - 'A' as u32 + 1)
+    });
+    Some((row.parse().ok()?, col - 1))
+}
+
+fn cell_ref(row: u32, col: u32) -> String {
+    format!("{}{}", column_number_to_name(col as u16), row)
+}
+
+/// An existing `<c>` element and everything inside it.
+struct Cell<'a> {
+    col: u32,
+    style: Option<String>,
+    events: Vec<Event<'a>>,
+}
+
+fn patch_sheet_xml(xml: &str, patch: &SheetPatch) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    let decoder = reader.decoder();
+    let mut writer = Writer::new(Vec::new());
+    let mut pending = patch.cells.iter().peekable();
+
+    loop {
+        let event = reader.read_event()?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"row" => {
+                let row = attribute(e, b"r", decoder)?
+                    .and_then(|r| r.parse::<u32>().ok())
+                    .ok_or("Row without a row number")?;
+
+                // Rows that exist only in the patch go before this one
+                while let Some((&patch_row, cells)) = pending.next_if(|(r, _)| **r < row) {
+                    write_row(&mut writer, patch_row, None, Vec::new(), cells)?;
+                }
+
+                let is_empty = matches!(event, Event::Empty(_));
+                match pending.next_if(|(r, _)| **r == row) {
+                    Some((_, cells)) => {
+                        let existing = if is_empty {
+                            Vec::new()
+                        } else {
+                            read_row_cells(&mut reader)?
+                        };
+                        write_row(&mut writer, row, Some(e), existing, cells)?;
+                    }
+                    None => writer.write_event(event)?,
+                }
+            }
+            Event::End(ref e) if e.local_name().as_ref() == b"sheetData" => {
+                for (&patch_row, cells) in pending.by_ref() {
+                    write_row(&mut writer, patch_row, None, Vec::new(), cells)?;
+                }
+                writer.write_event(event)?;
+            }
+            Event::Empty(ref e) if e.local_name().as_ref() == b"dimension" => {
+                let dimension = attribute(e, b"ref", decoder)?.unwrap_or_default();
+                writer
+                    .write_event(Event::Empty(BytesStart::new("dimension").with_attributes(
+                        [("ref", extend_dimension(&dimension, patch).as_str())],
+                    )))?;
+            }
+            Event::Eof => break,
+            _ => writer.write_event(event)?,
+        }
+    }
+
+    Ok(writer.into_inner())
+}
+
+/// Grows a `<dimension ref="A1:E10"/>` range to cover every patched cell.
+fn extend_dimension(dimension: &str, patch: &SheetPatch) -> String {
+    let (first, last) = dimension.split_once(':').unwrap_or((dimension, dimension));
+    let (first_row, first_col) = parse_cell_ref(first).unwrap_or((1, 0));
+    let (mut last_row, mut last_col) = parse_cell_ref(last).unwrap_or((first_row, first_col));
+    for (&row, cells) in patch.cells.iter() {
+        last_row = last_row.max(row);
+        if let Some((&col, _)) = cells.last_key_value() {
+            last_col = last_col.max(col);
+        }
+    }
+    format!(
+        "{}:{}",
+        cell_ref(first_row, first_col),
+        cell_ref(last_row, last_col)
+    )
+}
+
+/// Reads the cells of the current row up to and including `</row>`.
+fn read_row_cells<'a>(reader: &mut Reader<&'a [u8]>) -> Result<Vec<Cell<'a>>, Box<dyn Error>> {
+    let decoder = reader.decoder();
+    let mut cells: Vec<Cell> = Vec::new();
+    loop {
+        let event = reader.read_event()?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) if e.local_name().as_ref() == b"c" => {
+                let col = match attribute(e, b"r", decoder)?.and_then(|r| parse_cell_ref(&r)) {
+                    Some((_, col)) => col,
+                    // The reference is optional; such cells follow the previous one
+                    None => cells.last().map(|c| c.col + 1).unwrap_or(0),
+                };
+                let style = attribute(e, b"s", decoder)?;
+                let is_start = matches!(event, Event::Start(_));
+                let mut events = vec![event];
+                if is_start {
+                    loop {
+                        let inner = reader.read_event()?;
+                        let done = matches!(inner, Event::End(ref end) if end.local_name().as_ref() == b"c");
+                        events.push(inner);
+                        if done {
+                            break;
+                        }
+                    }
+                }
+                cells.push(Cell { col, style, events });
+            }
+            Event::End(ref e) if e.local_name().as_ref() == b"row" => break,
+            Event::Eof => return Err("Unexpected end of sheet inside a row".into()),
+            // Whitespace between cells is dropped; anything else stays with the previous cell
+            Event::Text(ref t) if t.iter().all(u8::is_ascii_whitespace) => {}
+            other => match cells.last_mut() {
+                Some(cell) => cell.events.push(other),
+                None => return Err("Unexpected content in row".into()),
+            },
+        }
+    }
+    Ok(cells)
+}
+
+fn write_row(
+    writer: &mut Writer<Vec<u8>>,
+    row: u32,
+    start: Option<&BytesStart>,
+    existing: Vec<Cell>,
+    updates: &BTreeMap<u32, CellValue>,
+) -> Result<(), Box<dyn Error>> {
+    // `spans` is only an optimisation hint and may no longer be accurate
+    let mut row_start = BytesStart::new("row");
+    match start {
+        Some(start) => {
+            for attr in start.attributes().flatten() {
+                if attr.key.as_ref() != b"spans" {
+                    row_start.push_attribute(attr);
+                }
+            }
+        }
+        None => row_start.push_attribute(("r", row.to_string().as_str())),
+    }
+    writer.write_event(Event::Start(row_start))?;
+
+    let mut existing = existing.into_iter().peekable();
+    let mut last_style: Option<String> = None;
+    for (&col, value) in updates.iter() {
+        while let Some(cell) = existing.next_if(|c| c.col < col) {
+            last_style = cell.style.clone();
+            for event in cell.events {
+                writer.write_event(event)?;
+            }
+        }
+        // Replaced cells keep their own style, new cells borrow their left neighbour's
+        let style = match existing.next_if(|c| c.col == col) {
+            Some(cell) => cell.style,
+            None => last_style.clone(),
+        };
+        write_cell(writer, row, col, value, style.as_deref())?;
+    }
+    for cell in existing {
+        for event in cell.events {
+            writer.write_event(event)?;
+        }
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("row")))?;
+    Ok(())
+}
+
+fn write_cell(
+    writer: &mut Writer<Vec<u8>>,
+    row: u32,
+    col: u32,
+    value: &CellValue,
+    style: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let reference = cell_ref(row, col);
+    let mut cell = BytesStart::new("c");
+    cell.push_attribute(("r", reference.as_str()));
+    if let Some(style) = style {
+        cell.push_attribute(("s", style));
+    }
+
+    match value {
+        CellValue::Text(text) => {
+            // Inline strings avoid touching the shared string table
+            cell.push_attribute(("t", "inlineStr"));
+            writer.write_event(Event::Start(cell))?;
+            writer.write_event(Event::Start(BytesStart::new("is")))?;
+            writer.write_event(Event::Start(
+                BytesStart::new("t").with_attributes([("xml:space", "preserve")]),
+            ))?;
+            writer.write_event(Event::Text(BytesText::new(text)))?;
+            writer.write_event(Event::End(BytesEnd::new("t")))?;
+            writer.write_event(Event::End(BytesEnd::new("is")))?;
+        }
+        CellValue::Number(number) => {
+            writer.write_event(Event::Start(cell))?;
+            writer.write_event(Event::Start(BytesStart::new("v")))?;
+            writer.write_event(Event::Text(BytesText::new(&number.to_string())))?;
+            writer.write_event(Event::End(BytesEnd::new("v")))?;
+        }
+    }
+    writer.write_event(Event::End(BytesEnd::new("c")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cell_ref() {
+        assert_eq!(parse_cell_ref("A1"), Some((1, 0)));
+        assert_eq!(parse_cell_ref("E10"), Some((10, 4)));
+        assert_eq!(parse_cell_ref("AB3"), Some((3, 27)));
+        assert_eq!(parse_cell_ref("12"), None);
+    }
+
+    #[test]
+    fn test_patch_sheet_xml_replaces_and_appends_cells() {
+        let xml = r#"<worksheet><dimension ref="A1:B2"/><sheetData><row r="1" spans="1:2"><c r="A1" t="s" s="3"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row><row r="2"><c r="A2" s="4"><v>7</v></c><c r="B2"><v>8</v></c></row></sheetData></worksheet>"#;
+        let mut patch = SheetPatch::new("Sheet1");
+        patch.set(1, 2, CellValue::Text("Status".to_string()));
+        patch.set(2, 0, CellValue::Number(4.5));
+        patch.set(2, 2, CellValue::Text("OK & done".to_string()));
+
+        let patched = String::from_utf8(patch_sheet_xml(xml, &patch).unwrap()).unwrap();
+
+        assert!(patched.contains(r#"<dimension ref="A1:C2"/>"#));
+        assert!(patched.contains(r#"<row r="1"><c r="A1" t="s" s="3">"#));
+        assert!(patched.contains(
+            r#"<c r="C1" t="inlineStr"><is><t xml:space="preserve">Status</t></is></c>"#
+        ));
+        assert!(patched.contains(r#"<c r="A2" s="4"><v>4.5</v></c><c r="B2"><v>8</v></c>"#));
+        assert!(patched.contains("OK &amp; done"));
+    }
+}